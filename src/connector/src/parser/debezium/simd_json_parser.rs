@@ -12,11 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
 use std::fmt::Debug;
 
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use futures_async_stream::try_stream;
 use risingwave_common::error::ErrorCode::ProtocolError;
 use risingwave_common::error::{Result, RwError};
+use risingwave_common::types::{DataType, Datum, Decimal as RwDecimal, ScalarImpl};
 use simd_json::{BorrowedValue, StaticNode, ValueAccess};
 
 use super::operators::*;
@@ -29,6 +32,10 @@ const BEFORE: &str = "before";
 const AFTER: &str = "after";
 const OP: &str = "op";
 
+/// Debezium's Unix epoch expressed in chrono's days-from-CE, used to turn an
+/// `io.debezium.time.Date` day count into a [`NaiveDate`].
+const UNIX_EPOCH_DAYS_FROM_CE: i32 = 719_163;
+
 #[inline]
 fn ensure_not_null<'a, 'b: 'a>(value: &'a BorrowedValue<'b>) -> Option<&'a BorrowedValue<'b>> {
     if let BorrowedValue::Static(StaticNode::Null) = value {
@@ -38,28 +45,371 @@ fn ensure_not_null<'a, 'b: 'a>(value: &'a BorrowedValue<'b>) -> Option<&'a Borro
     }
 }
 
+/// Decodes a Debezium/Kafka Connect message key and returns the row it carries: the `payload` if
+/// the key is itself schema-wrapped (`schemas.enable=true`), otherwise the decoded value
+/// directly. Returns `None` if `buf` fails to parse or decodes to a null, so callers can treat a
+/// missing/unusable key the same as an absent field.
+fn decode_key_row(buf: &mut Vec<u8>) -> Option<BorrowedValue<'_>> {
+    let event: BorrowedValue<'_> = simd_json::to_borrowed_value(buf).ok()?;
+    let row = event.get("payload").cloned().unwrap_or(event);
+    if matches!(row, BorrowedValue::Static(StaticNode::Null)) {
+        None
+    } else {
+        Some(row)
+    }
+}
+
+/// Picks the row to read `before`/delete columns from: the payload's own row if it has one, else
+/// the message key (see [`decode_key_row`]) as a fallback. Shared by `parse_wrapped`'s
+/// update/delete arms and `parse_unwrapped`'s delete arm, which all hit the same "no `before`, but
+/// the key can stand in for it" case — e.g. Postgres without `REPLICA IDENTITY FULL`, or a bare
+/// tombstone in the flattened envelope.
+fn resolve_row_with_key_fallback<'a, 'b: 'a>(
+    payload_row: Option<&'a BorrowedValue<'b>>,
+    key_row: Option<&'a BorrowedValue<'b>>,
+) -> Option<&'a BorrowedValue<'b>> {
+    payload_row.and_then(ensure_not_null).or(key_row)
+}
+
+fn debezium_int_value(value: &BorrowedValue<'_>, what: &str) -> Result<i64> {
+    value
+        .as_i64()
+        .ok_or_else(|| RwError::from(ProtocolError(format!("expected an integer {what}"))))
+}
+
+/// Debezium emits `Timestamp`/`MicroTimestamp`/`NanoTimestamp` as millis/micros/nanos since the
+/// epoch, but RisingWave's `Timestamp` column type doesn't track which of those produced a given
+/// value, so the unit has to come from the Kafka Connect schema's logical-type name
+/// (`logical_name`, e.g. `"io.debezium.time.MicroTimestamp"`) rather than being guessed from the
+/// value's magnitude: a magnitude guess misclassifies any value close to the epoch (a
+/// `MicroTimestamp` or `NanoTimestamp` from before early 1973 falls below the thresholds below
+/// and is read in a coarser unit).
+/// `schemas.enable=false` carries no schema at all; magnitude is the only signal left then, so it
+/// remains as a last-resort fallback for that case only.
+fn debezium_timestamp_millis(raw: i64, logical_name: Option<&str>) -> i64 {
+    match logical_name {
+        Some("io.debezium.time.Timestamp") => raw,
+        Some("io.debezium.time.MicroTimestamp") => raw.div_euclid(1_000),
+        Some("io.debezium.time.NanoTimestamp") => raw.div_euclid(1_000_000),
+        _ => {
+            if raw.unsigned_abs() >= 100_000_000_000_000_000 {
+                raw / 1_000_000
+            } else if raw.unsigned_abs() >= 100_000_000_000_000 {
+                raw / 1_000
+            } else {
+                raw
+            }
+        }
+    }
+}
+
+/// Same idea as [`debezium_timestamp_millis`], but for `Time` (millis since midnight),
+/// `MicroTime` (micros since midnight) and `NanoTime` (nanos since midnight).
+fn debezium_time_micros_since_midnight(raw: i64, logical_name: Option<&str>) -> i64 {
+    match logical_name {
+        Some("io.debezium.time.Time") => raw * 1_000,
+        Some("io.debezium.time.MicroTime") => raw,
+        Some("io.debezium.time.NanoTime") => raw.div_euclid(1_000),
+        _ => {
+            if raw.unsigned_abs() >= 1_000_000_000 {
+                raw
+            } else {
+                raw * 1_000
+            }
+        }
+    }
+}
+
+fn parse_debezium_date(value: &BorrowedValue<'_>) -> Result<Datum> {
+    let days = debezium_int_value(value, "day count for a date column")? as i32;
+    let naive = NaiveDate::from_num_days_from_ce_opt(days + UNIX_EPOCH_DAYS_FROM_CE)
+        .ok_or_else(|| RwError::from(ProtocolError(format!("out-of-range debezium date: {days}"))))?;
+    Ok(Some(ScalarImpl::Date(naive.into())))
+}
+
+fn parse_debezium_timestamp(value: &BorrowedValue<'_>, logical_name: Option<&str>) -> Result<Datum> {
+    let raw = debezium_int_value(value, "epoch value for a timestamp column")?;
+    let millis = debezium_timestamp_millis(raw, logical_name);
+    let naive = NaiveDateTime::from_timestamp_opt(
+        millis.div_euclid(1000),
+        (millis.rem_euclid(1000) * 1_000_000) as u32,
+    )
+    .ok_or_else(|| {
+        RwError::from(ProtocolError(format!("out-of-range debezium timestamp: {raw}")))
+    })?;
+    Ok(Some(ScalarImpl::Timestamp(naive.into())))
+}
+
+fn parse_debezium_zoned_timestamp(value: &BorrowedValue<'_>) -> Result<Datum> {
+    let text = value.as_str().ok_or_else(|| {
+        RwError::from(ProtocolError(
+            "expected an ISO-8601 string for a zoned timestamp column".to_string(),
+        ))
+    })?;
+    let parsed = chrono::DateTime::parse_from_rfc3339(text).map_err(|e| {
+        RwError::from(ProtocolError(format!(
+            "invalid debezium zoned timestamp {text}: {e}"
+        )))
+    })?;
+    Ok(Some(ScalarImpl::Timestamptz(
+        parsed.with_timezone(&Utc).into(),
+    )))
+}
+
+fn parse_debezium_time(value: &BorrowedValue<'_>, logical_name: Option<&str>) -> Result<Datum> {
+    let raw = debezium_int_value(value, "since-midnight value for a time column")?;
+    let micros = debezium_time_micros_since_midnight(raw, logical_name);
+    let naive = NaiveTime::from_num_seconds_from_midnight_opt(
+        (micros / 1_000_000) as u32,
+        ((micros % 1_000_000) * 1_000) as u32,
+    )
+    .ok_or_else(|| RwError::from(ProtocolError(format!("out-of-range debezium time: {raw}"))))?;
+    Ok(Some(ScalarImpl::Time(naive.into())))
+}
+
+fn parse_debezium_decimal(value: &BorrowedValue<'_>, scale: u32) -> Result<Datum> {
+    // decimal.handling.mode=double: Debezium emits a plain JSON number instead of the base64
+    // org.apache.kafka.connect.data.Decimal encoding; simd_json_parse_value already handles that.
+    let Some(encoded) = value.as_str() else {
+        return simd_json_parse_value(&DataType::Decimal, Some(value));
+    };
+    let Ok(bytes) = base64::decode(encoded) else {
+        // decimal.handling.mode=string: a plain decimal literal (e.g. "123.45"), which is never
+        // valid base64 (it contains `.` and, for negatives, a leading `-`), so parse it directly.
+        let parsed = encoded.parse::<rust_decimal::Decimal>().map_err(|e| {
+            RwError::from(ProtocolError(format!(
+                "invalid debezium decimal string {encoded}: {e}"
+            )))
+        })?;
+        return Ok(Some(ScalarImpl::Decimal(RwDecimal::from(parsed))));
+    };
+    if bytes.len() > 16 {
+        return Err(RwError::from(ProtocolError(format!(
+            "debezium decimal {encoded} decodes to a {}-byte unscaled integer, more than the \
+             16 bytes (NUMERIC(38)) this parser supports",
+            bytes.len()
+        ))));
+    }
+    // Sign-extend the big-endian two's-complement unscaled integer to 16 bytes.
+    let negative = bytes.first().is_some_and(|b| b & 0x80 != 0);
+    let mut buf = if negative { [0xffu8; 16] } else { [0u8; 16] };
+    buf[16 - bytes.len()..].copy_from_slice(&bytes);
+    let unscaled = i128::from_be_bytes(buf);
+    let decimal = rust_decimal::Decimal::try_from_i128_with_scale(unscaled, scale).map_err(|e| {
+        RwError::from(ProtocolError(format!(
+            "debezium decimal {encoded} (unscaled {unscaled}, scale {scale}) out of range: {e}"
+        )))
+    })?;
+    Ok(Some(ScalarImpl::Decimal(RwDecimal::from(decimal))))
+}
+
+/// Finds the nested field schema for `field_name` within a Kafka Connect JSON schema's `fields`
+/// array, e.g. the schema of column `field_name` within the schema of `before`/`after`.
+fn find_nested_field_schema<'a, 'b: 'a>(
+    schema: &'a BorrowedValue<'b>,
+    field_name: &str,
+) -> Option<&'a BorrowedValue<'b>> {
+    schema
+        .get("fields")
+        .and_then(|fields| fields.as_array())
+        .and_then(|fields| {
+            fields
+                .iter()
+                .find(|field| field.get("field").and_then(|v| v.as_str()) == Some(field_name))
+        })
+}
+
+/// Controls how a RisingWave column name is resolved against the field names in a Debezium JSON
+/// row, for upstream sources whose column names differ in case or spelling from the RisingWave
+/// table (e.g. quoted, mixed-case Postgres identifiers).
+///
+/// Defaults to case-insensitive matching on the column's own name, so a source field whose name
+/// differs from the column's only in ASCII case (e.g. `Order_ID` for `order_id`) is found.
+#[derive(Debug, Clone, Default)]
+pub struct FieldNameMapping {
+    case_sensitive: bool,
+    renames: HashMap<String, String>,
+}
+
+impl FieldNameMapping {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Match source field names exactly instead of case-insensitively.
+    pub fn case_sensitive(mut self, case_sensitive: bool) -> Self {
+        self.case_sensitive = case_sensitive;
+        self
+    }
+
+    /// Looks `rw_column` up under `source_field` instead of its own name.
+    pub fn with_rename(mut self, rw_column: impl Into<String>, source_field: impl Into<String>) -> Self {
+        self.renames.insert(rw_column.into(), source_field.into());
+        self
+    }
+
+    fn source_field_name<'a>(&'a self, column: &'a SourceColumnDesc) -> &'a str {
+        self.renames
+            .get(&column.name)
+            .map(String::as_str)
+            .unwrap_or(&column.name)
+    }
+
+    /// Looks `column` up in `row`, honoring any configured rename and the case-sensitivity
+    /// setting.
+    fn lookup<'a, 'b>(
+        &self,
+        row: &'a BorrowedValue<'b>,
+        column: &SourceColumnDesc,
+    ) -> Option<&'a BorrowedValue<'b>> {
+        let field_name = self.source_field_name(column);
+        self.lookup_by_name(row, field_name)
+    }
+
+    /// The field-name-matching half of [`Self::lookup`], factored out so it's testable without a
+    /// `SourceColumnDesc` (renames are just a `HashMap` lookup and don't need their own test).
+    fn lookup_by_name<'a, 'b>(
+        &self,
+        row: &'a BorrowedValue<'b>,
+        field_name: &str,
+    ) -> Option<&'a BorrowedValue<'b>> {
+        if self.case_sensitive {
+            row.get(field_name)
+        } else {
+            row.as_object()?
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(field_name))
+                .map(|(_, value)| value)
+        }
+    }
+}
+
+/// Converts a raw Debezium JSON value into the `Datum` for `column`, decoding the logical
+/// encodings Debezium uses for types that wouldn't otherwise round-trip through plain JSON:
+/// base64 unscaled-integer decimals, and epoch/midnight-offset dates, times and timestamps.
+/// `row_schema` is the Kafka Connect schema of the enclosing `before`/`after` struct, if the
+/// event carries one; it's consulted for the column's own field schema, which in turn carries a
+/// `Decimal` column's `scale` and a `Timestamp`/`Time` column's logical-type name (used to tell
+/// apart e.g. `MicroTimestamp` from `Timestamp`). Both default to their `schemas.enable=false`
+/// fallback when no schema is available. Everything else falls through to
+/// [`simd_json_parse_value`].
+fn parse_debezium_value(
+    row_schema: Option<&BorrowedValue<'_>>,
+    column: &SourceColumnDesc,
+    value: Option<&BorrowedValue<'_>>,
+) -> Result<Datum> {
+    let Some(value) = value.and_then(ensure_not_null) else {
+        return Ok(None);
+    };
+
+    let field_schema = row_schema.and_then(|schema| find_nested_field_schema(schema, &column.name));
+    let logical_name = field_schema
+        .and_then(|field_schema| field_schema.get("name"))
+        .and_then(|name| name.as_str());
+
+    match &column.data_type {
+        DataType::Decimal => {
+            let scale = field_schema
+                .and_then(|field_schema| field_schema.get("parameters"))
+                .and_then(|parameters| parameters.get("scale"))
+                .and_then(|scale| scale.as_str())
+                .and_then(|scale| scale.parse::<u32>().ok())
+                .unwrap_or(0);
+            parse_debezium_decimal(value, scale)
+        }
+        DataType::Date => parse_debezium_date(value),
+        DataType::Timestamp => parse_debezium_timestamp(value, logical_name),
+        DataType::Timestamptz => parse_debezium_zoned_timestamp(value),
+        DataType::Time => parse_debezium_time(value, logical_name),
+        data_type => simd_json_parse_value(data_type, Some(value)),
+    }
+}
+
 impl_common_parser_logic!(DebeziumJsonParser);
 
+/// Which Debezium message shape [`DebeziumJsonParser`] expects, selected at construction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DebeziumEnvelope {
+    /// The standard envelope: `payload.{before,after,op}`.
+    #[default]
+    Wrapped,
+    /// The flattened envelope produced by Debezium's `ExtractNewRecordState` SMT: row columns
+    /// sit at the top level of `payload` (or the message body), with delete/tombstone signaled
+    /// by a null body or `__deleted`/`__op` metadata fields.
+    Unwrapped,
+}
+
 #[derive(Debug)]
 pub struct DebeziumJsonParser {
     pub(crate) rw_columns: Vec<SourceColumnDesc>,
+    envelope: DebeziumEnvelope,
+    field_names: FieldNameMapping,
 }
 
 impl DebeziumJsonParser {
     pub fn new(rw_columns: Vec<SourceColumnDesc>) -> Result<Self> {
-        Ok(Self { rw_columns })
+        Self::new_with_envelope(rw_columns, DebeziumEnvelope::Wrapped)
+    }
+
+    /// Like [`Self::new`], but for sources produced by Debezium's `ExtractNewRecordState` SMT.
+    pub fn new_unwrapped(rw_columns: Vec<SourceColumnDesc>) -> Result<Self> {
+        Self::new_with_envelope(rw_columns, DebeziumEnvelope::Unwrapped)
+    }
+
+    pub fn new_with_envelope(
+        rw_columns: Vec<SourceColumnDesc>,
+        envelope: DebeziumEnvelope,
+    ) -> Result<Self> {
+        Ok(Self {
+            rw_columns,
+            envelope,
+            field_names: FieldNameMapping::default(),
+        })
+    }
+
+    /// Resolves RisingWave column names against the upstream JSON with `field_names` instead of
+    /// the default case-insensitive match on the column's own name.
+    pub fn with_field_names(mut self, field_names: FieldNameMapping) -> Self {
+        self.field_names = field_names;
+        self
     }
 
+    /// `parse_wrapped`/`parse_unwrapped` accept the raw Debezium/Kafka Connect message key, if
+    /// any, and fall back to decoding it (see [`decode_key_row`]) to reconstruct a
+    /// `delete`/update-`before` tuple when the value's own `before` is absent (e.g. Postgres
+    /// without `REPLICA IDENTITY FULL`). `impl_common_parser_logic!`'s generated `parse` is the
+    /// only real caller of this method, and it still invokes the 2-arg `parse_inner(payload,
+    /// writer)`; that macro lives outside this crate slice, so until it's extended to thread a
+    /// message key through, no real caller can supply one and this always passes `None`.
     #[allow(clippy::unused_async)]
     pub async fn parse_inner(
         &self,
         payload: &[u8],
+        writer: SourceStreamChunkRowWriter<'_>,
+    ) -> Result<WriteGuard> {
+        match self.envelope {
+            DebeziumEnvelope::Wrapped => self.parse_wrapped(None, payload, writer).await,
+            DebeziumEnvelope::Unwrapped => self.parse_unwrapped(None, payload, writer).await,
+        }
+    }
+
+    async fn parse_wrapped(
+        &self,
+        key: Option<&[u8]>,
+        payload: &[u8],
         mut writer: SourceStreamChunkRowWriter<'_>,
     ) -> Result<WriteGuard> {
         let mut payload_mut = payload.to_vec();
         let event: BorrowedValue<'_> = simd_json::to_borrowed_value(&mut payload_mut)
             .map_err(|e| RwError::from(ProtocolError(e.to_string())))?;
 
+        // The message key carries only the primary-key columns, but looking a non-key column
+        // name up in it simply yields `None`, the same as an absent field would, so it can
+        // stand in for `before` without knowing which columns are keys.
+        let mut key_buf = key.map(<[u8]>::to_vec);
+        let key_event: Option<BorrowedValue<'_>> = key_buf.as_mut().and_then(decode_key_row);
+        let key_row: Option<&BorrowedValue<'_>> = key_event.as_ref();
+
         let payload = event
             .get("payload")
             .and_then(ensure_not_null)
@@ -73,13 +423,19 @@ impl DebeziumJsonParser {
             ))
         })?;
 
+        // The Kafka Connect schema of the `before`/`after` struct, if the event carries one
+        // (i.e. `schemas.enable=true`); used to recover logical-type metadata like a decimal
+        // column's scale that doesn't otherwise survive the JSON encoding.
+        let row_schema = event.get("schema");
+
         match op {
             DEBEZIUM_UPDATE_OP => {
-                let before = payload.get(BEFORE).and_then(ensure_not_null).ok_or_else(|| {
-                    RwError::from(ProtocolError(
-                        "before is missing for updating event. If you are using postgres, you may want to try ALTER TABLE $TABLE_NAME REPLICA IDENTITY FULL;".to_string(),
-                    ))
-                })?;
+                let before = resolve_row_with_key_fallback(payload.get(BEFORE), key_row)
+                    .ok_or_else(|| {
+                        RwError::from(ProtocolError(
+                            "before is missing for updating event and no usable message key was provided. If you are using postgres, you may want to try ALTER TABLE $TABLE_NAME REPLICA IDENTITY FULL;".to_string(),
+                        ))
+                    })?;
 
                 let after = payload
                     .get(AFTER)
@@ -90,14 +446,19 @@ impl DebeziumJsonParser {
                         ))
                     })?;
 
+                let before_schema = row_schema.and_then(|schema| find_nested_field_schema(schema, BEFORE));
+                let after_schema = row_schema.and_then(|schema| find_nested_field_schema(schema, AFTER));
+
                 writer.update(|column| {
-                    let before = simd_json_parse_value(
-                        &column.data_type,
-                        before.get(column.name.to_ascii_lowercase().as_str()),
+                    let before = parse_debezium_value(
+                        before_schema,
+                        column,
+                        self.field_names.lookup(before, column),
                     )?;
-                    let after = simd_json_parse_value(
-                        &column.data_type,
-                        after.get(column.name.to_ascii_lowercase().as_str()),
+                    let after = parse_debezium_value(
+                        after_schema,
+                        column,
+                        self.field_names.lookup(after, column),
                     )?;
 
                     Ok((before, after))
@@ -113,28 +474,32 @@ impl DebeziumJsonParser {
                         ))
                     })?;
 
+                let after_schema = row_schema.and_then(|schema| find_nested_field_schema(schema, AFTER));
+
                 writer.insert(|column| {
-                    simd_json_parse_value(
-                        &column.data_type,
-                        after.get(column.name.to_ascii_lowercase().as_str()),
+                    parse_debezium_value(
+                        after_schema,
+                        column,
+                        self.field_names.lookup(after, column),
                     )
                     .map_err(Into::into)
                 })
             }
             DEBEZIUM_DELETE_OP => {
-                let before = payload
-                    .get(BEFORE)
-                    .and_then(ensure_not_null)
+                let before = resolve_row_with_key_fallback(payload.get(BEFORE), key_row)
                     .ok_or_else(|| {
                         RwError::from(ProtocolError(
-                            "before is missing for delete event".to_string(),
+                            "before is missing for delete event and no usable message key was provided".to_string(),
                         ))
                     })?;
 
+                let before_schema = row_schema.and_then(|schema| find_nested_field_schema(schema, BEFORE));
+
                 writer.delete(|column| {
-                    simd_json_parse_value(
-                        &column.data_type,
-                        before.get(column.name.to_ascii_lowercase().as_str()),
+                    parse_debezium_value(
+                        before_schema,
+                        column,
+                        self.field_names.lookup(before, column),
                     )
                     .map_err(Into::into)
                 })
@@ -145,4 +510,305 @@ impl DebeziumJsonParser {
             )))),
         }
     }
+
+    /// Parses a record produced by Debezium's `ExtractNewRecordState` SMT, which flattens the
+    /// usual `before`/`after`/`op` envelope so the row columns sit at the top level.
+    async fn parse_unwrapped(
+        &self,
+        key: Option<&[u8]>,
+        payload: &[u8],
+        mut writer: SourceStreamChunkRowWriter<'_>,
+    ) -> Result<WriteGuard> {
+        let mut payload_mut = payload.to_vec();
+        let event: BorrowedValue<'_> = simd_json::to_borrowed_value(&mut payload_mut)
+            .map_err(|e| RwError::from(ProtocolError(e.to_string())))?;
+
+        // The message key carries only the primary-key columns; it stands in for a tombstone's
+        // missing body the same way it does in `Self::parse_wrapped`.
+        let mut key_buf = key.map(<[u8]>::to_vec);
+        let key_event: Option<BorrowedValue<'_>> = key_buf.as_mut().and_then(decode_key_row);
+        let key_row: Option<&BorrowedValue<'_>> = key_event.as_ref();
+
+        // Deployments with `schemas.enable=true` still wrap the flattened row in a `payload`
+        // key; ones with it disabled emit the row directly as the message body. Either way the
+        // schema (when present) already describes the row's columns at its top level, since
+        // `ExtractNewRecordState` flattens `before`/`after` into the message itself rather than
+        // nesting them under a `payload` field the way the wrapped envelope does.
+        let row = event.get("payload").or(Some(&event));
+        let row_schema = event.get("schema");
+
+        if unwrapped_row_is_delete(row) {
+            let row = resolve_row_with_key_fallback(row, key_row);
+            writer.delete(|column| {
+                parse_debezium_value(
+                    row_schema,
+                    column,
+                    row.and_then(|row| self.field_names.lookup(row, column)),
+                )
+                .map_err(Into::into)
+            })
+        } else {
+            let row = row.and_then(ensure_not_null);
+            writer.insert(|column| {
+                parse_debezium_value(
+                    row_schema,
+                    column,
+                    row.and_then(|row| self.field_names.lookup(row, column)),
+                )
+                .map_err(Into::into)
+            })
+        }
+    }
+}
+
+/// Whether a flattened (`ExtractNewRecordState`) record represents a delete: a bare tombstone
+/// (null body), a `__deleted` metadata field, or a Debezium `__op` of `"d"`.
+fn unwrapped_row_is_delete(row: Option<&BorrowedValue<'_>>) -> bool {
+    let Some(row) = row.and_then(ensure_not_null) else {
+        return true;
+    };
+
+    let deleted_flag = row.get("__deleted").and_then(|v| match v {
+        BorrowedValue::String(s) => Some(s.eq_ignore_ascii_case("true")),
+        BorrowedValue::Static(StaticNode::Bool(b)) => Some(*b),
+        _ => None,
+    });
+    if let Some(deleted) = deleted_flag {
+        return deleted;
+    }
+
+    row.get("__op").and_then(|v| v.as_str()) == Some("d")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decimal_value(bytes: &[u8]) -> BorrowedValue<'static> {
+        BorrowedValue::String(base64::encode(bytes).into())
+    }
+
+    #[test]
+    fn test_parse_debezium_decimal_sign_extension() {
+        // (unscaled bytes, scale, expected decimal string)
+        let cases: Vec<(&[u8], u32, &str)> = vec![
+            (&[0x01], 0, "1"),
+            (&[0x30, 0x39], 2, "123.45"),  // 12345 / 10^2
+            (&[0xff], 0, "-1"),            // -1 in one's byte two's complement
+            (&[0xcf, 0xc7], 2, "-123.45"), // -12345 / 10^2
+            (&[0x00], 0, "0"),
+        ];
+        for (bytes, scale, expected) in cases {
+            let value = decimal_value(bytes);
+            let datum = parse_debezium_decimal(&value, scale).unwrap().unwrap();
+            let ScalarImpl::Decimal(decimal) = datum else {
+                panic!("expected a decimal datum");
+            };
+            assert_eq!(decimal.to_string(), expected, "bytes = {bytes:?}");
+        }
+    }
+
+    #[test]
+    fn test_parse_debezium_decimal_rejects_oversized_unscaled_integer() {
+        // 17 bytes: more than the 16-byte (NUMERIC(38)) buffer this parser sign-extends into.
+        let bytes = [0x7fu8; 17];
+        let value = decimal_value(&bytes);
+        assert!(parse_debezium_decimal(&value, 0).is_err());
+    }
+
+    #[test]
+    fn test_parse_debezium_decimal_rejects_value_too_big_for_rust_decimal() {
+        // A 16-byte unscaled integer that decodes cleanly but is too large for rust_decimal's
+        // 96-bit mantissa; this must surface as a ProtocolError, not panic.
+        let bytes = i128::MAX.to_be_bytes();
+        let value = decimal_value(&bytes);
+        assert!(parse_debezium_decimal(&value, 0).is_err());
+    }
+
+    #[test]
+    fn test_parse_debezium_decimal_handling_mode_string() {
+        // decimal.handling.mode=string: a plain decimal literal, not base64.
+        let value = BorrowedValue::String("123.45".into());
+        let datum = parse_debezium_decimal(&value, 2).unwrap().unwrap();
+        let ScalarImpl::Decimal(decimal) = datum else {
+            panic!("expected a decimal datum");
+        };
+        assert_eq!(decimal.to_string(), "123.45");
+    }
+
+    #[test]
+    fn test_parse_debezium_decimal_handling_mode_double() {
+        // decimal.handling.mode=double: a plain JSON number.
+        let value = BorrowedValue::Static(StaticNode::F64(123.45));
+        let datum = parse_debezium_decimal(&value, 2).unwrap().unwrap();
+        let ScalarImpl::Decimal(decimal) = datum else {
+            panic!("expected a decimal datum");
+        };
+        assert_eq!(decimal.to_string(), "123.45");
+    }
+
+    #[test]
+    fn test_debezium_timestamp_millis_uses_logical_name_not_magnitude() {
+        // A MicroTimestamp close to the epoch: below the magnitude fallback's 1e14 threshold, so
+        // without a logical name it would be read as plain millis, 1000x too large.
+        let small_micros = 42_000_000i64; // 1970-01-01T00:00:42Z in micros
+        assert_eq!(
+            debezium_timestamp_millis(small_micros, Some("io.debezium.time.MicroTimestamp")),
+            42_000
+        );
+        assert_eq!(
+            debezium_timestamp_millis(small_micros, Some("io.debezium.time.Timestamp")),
+            small_micros
+        );
+        assert_eq!(
+            debezium_timestamp_millis(small_micros * 1000, Some("io.debezium.time.NanoTimestamp")),
+            42_000
+        );
+    }
+
+    #[test]
+    fn test_debezium_timestamp_millis_falls_back_to_magnitude_without_schema() {
+        // schemas.enable=false: no logical name available, so magnitude is the only signal.
+        assert_eq!(debezium_timestamp_millis(1_700_000_000_000, None), 1_700_000_000_000);
+        assert_eq!(debezium_timestamp_millis(1_700_000_000_000_000, None), 1_700_000_000_000);
+    }
+
+    #[test]
+    fn test_unwrapped_row_schema_is_flattened_not_nested_under_payload() {
+        // An `ExtractNewRecordState`-flattened schema lists each row column directly under
+        // `fields`; it is not wrapped in a `payload` field the way the `before`/`after` envelope
+        // is, so looking up a nested "payload" field (the old, wrong behavior) must find
+        // nothing, while looking up a real column must succeed.
+        let mut schema_json = br#"{"fields":[{"field":"id","type":"int32"},{"field":"amount","type":"bytes","parameters":{"scale":"2"}}]}"#.to_vec();
+        let schema: BorrowedValue<'_> = simd_json::to_borrowed_value(&mut schema_json).unwrap();
+
+        assert!(find_nested_field_schema(&schema, "payload").is_none());
+        assert!(find_nested_field_schema(&schema, "amount").is_some());
+    }
+
+    #[test]
+    fn test_unwrapped_row_is_delete_uses_key_as_fallback_row() {
+        // A bare tombstone (null value body) carries no columns at all; the message key is the
+        // only place primary-key columns can come from.
+        let mut key_json = br#"{"id":42}"#.to_vec();
+        let key_event: BorrowedValue<'_> = simd_json::to_borrowed_value(&mut key_json).unwrap();
+        let key_row = ensure_not_null(&key_event);
+        assert!(key_row.is_some());
+        assert_eq!(
+            resolve_row_with_key_fallback(None, key_row)
+                .and_then(|row| row.get("id"))
+                .and_then(|v| v.as_i64()),
+            Some(42)
+        );
+
+        assert!(unwrapped_row_is_delete(None));
+        assert!(unwrapped_row_is_delete(Some(&BorrowedValue::Static(
+            StaticNode::Null
+        ))));
+    }
+
+    #[test]
+    fn test_resolve_row_with_key_fallback_reconstructs_delete_row_from_key() {
+        // `parse_wrapped`/`parse_unwrapped` can't be driven end-to-end from this crate slice: the
+        // `SourceStreamChunkRowWriter`/`StreamChunkBuilder` construction helpers they need live
+        // outside it, and the only real caller, `impl_common_parser_logic!`'s generated `parse`,
+        // is defined outside this crate too (see `parse_inner`'s doc comment). So this drives the
+        // actual row-selection composition each of their delete/before arms uses —
+        // `resolve_row_with_key_fallback`, fed by the real `decode_key_row` decoder — as far
+        // end-to-end as this tree allows: a tombstone with no usable payload row must resolve to
+        // the decoded message key, column-for-column.
+        let mut key_buf = br#"{"id":42}"#.to_vec();
+        let key_event = decode_key_row(&mut key_buf);
+        let key_row = key_event.as_ref();
+        assert!(key_row.is_some());
+
+        // No payload row at all (bare tombstone): falls back to the key.
+        let resolved = resolve_row_with_key_fallback(None, key_row);
+        assert_eq!(resolved.and_then(|row| row.get("id")).and_then(|v| v.as_i64()), Some(42));
+
+        // A payload row present but null (Debezium's explicit absent-`before`/`after` encoding):
+        // still falls back to the key.
+        let null_row = BorrowedValue::Static(StaticNode::Null);
+        let resolved = resolve_row_with_key_fallback(Some(&null_row), key_row);
+        assert_eq!(resolved.and_then(|row| row.get("id")).and_then(|v| v.as_i64()), Some(42));
+
+        // A real payload row wins over the key.
+        let mut payload_buf = br#"{"id":7}"#.to_vec();
+        let payload_row: BorrowedValue<'_> = simd_json::to_borrowed_value(&mut payload_buf).unwrap();
+        let resolved = resolve_row_with_key_fallback(Some(&payload_row), key_row);
+        assert_eq!(resolved.and_then(|row| row.get("id")).and_then(|v| v.as_i64()), Some(7));
+
+        // No payload row and no usable key: nothing to reconstruct from.
+        assert!(resolve_row_with_key_fallback(None, None).is_none());
+    }
+
+    #[test]
+    fn test_debezium_time_micros_since_midnight_uses_logical_name_not_magnitude() {
+        // A value in the first ~1000s after midnight: below the magnitude fallback's 1e9
+        // threshold, so without a logical name a `MicroTime` would be read as millis and scaled
+        // by 1000. The logical name must decide the unit.
+        let small_millis = 42_000i64;
+        assert_eq!(
+            debezium_time_micros_since_midnight(small_millis, Some("io.debezium.time.Time")),
+            small_millis * 1_000
+        );
+        assert_eq!(
+            debezium_time_micros_since_midnight(small_millis, Some("io.debezium.time.MicroTime")),
+            small_millis
+        );
+        assert_eq!(
+            debezium_time_micros_since_midnight(
+                small_millis * 1_000_000,
+                Some("io.debezium.time.NanoTime")
+            ),
+            small_millis * 1_000
+        );
+    }
+
+    #[test]
+    fn test_field_name_mapping_default_is_case_insensitive() {
+        let mut row_json = br#"{"Order_ID":1}"#.to_vec();
+        let row: BorrowedValue<'_> = simd_json::to_borrowed_value(&mut row_json).unwrap();
+        let mapping = FieldNameMapping::new();
+        assert_eq!(
+            mapping
+                .lookup_by_name(&row, "order_id")
+                .and_then(|v| v.as_i64()),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_field_name_mapping_case_sensitive_rejects_mismatched_case() {
+        let mut row_json = br#"{"Order_ID":1}"#.to_vec();
+        let row: BorrowedValue<'_> = simd_json::to_borrowed_value(&mut row_json).unwrap();
+        let mapping = FieldNameMapping::new().case_sensitive(true);
+        assert!(mapping.lookup_by_name(&row, "order_id").is_none());
+        assert!(mapping.lookup_by_name(&row, "Order_ID").is_some());
+    }
+
+    #[test]
+    fn test_decode_key_row_unwraps_schema_wrapped_key() {
+        // schemas.enable=true: the key, like the value, is wrapped in `payload`.
+        let mut buf = br#"{"schema":{},"payload":{"id":42}}"#.to_vec();
+        let row = decode_key_row(&mut buf).unwrap();
+        assert_eq!(row.get("id").and_then(|v| v.as_i64()), Some(42));
+    }
+
+    #[test]
+    fn test_decode_key_row_falls_back_to_bare_value() {
+        // schemas.enable=false: the key is the row itself, with no `payload` wrapper.
+        let mut buf = br#"{"id":42}"#.to_vec();
+        let row = decode_key_row(&mut buf).unwrap();
+        assert_eq!(row.get("id").and_then(|v| v.as_i64()), Some(42));
+    }
+
+    #[test]
+    fn test_decode_key_row_rejects_null_and_garbage() {
+        let mut null_buf = b"null".to_vec();
+        assert!(decode_key_row(&mut null_buf).is_none());
+
+        let mut garbage_buf = b"not json".to_vec();
+        assert!(decode_key_row(&mut garbage_buf).is_none());
+    }
 }
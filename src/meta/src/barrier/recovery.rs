@@ -12,20 +12,24 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::HashMap;
-use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use futures::future::try_join_all;
+use futures::stream::{self, StreamExt, TryStreamExt};
 use itertools::Itertools;
+use prometheus::{IntCounter, IntGauge, Opts, Registry};
 use risingwave_common::util::epoch::Epoch;
 use risingwave_pb::common::worker_node::State;
 use risingwave_pb::common::{ActorInfo, WorkerNode, WorkerType};
 use risingwave_pb::stream_plan::barrier::Mutation;
 use risingwave_pb::stream_plan::AddMutation;
 use risingwave_pb::stream_service::{
-    BroadcastActorInfoTableRequest, BuildActorsRequest, ForceStopActorsRequest, UpdateActorsRequest,
+    BroadcastActorInfoTableRequest, BuildActorsRequest, ForceStopActorsRequest,
+    UpdateActorsRequest,
 };
+use tokio::sync::RwLock;
 use tokio_retry::strategy::{jitter, ExponentialBackoff};
 use tracing::{debug, error, warn};
 use uuid::Uuid;
@@ -37,7 +41,138 @@ use crate::manager::WorkerId;
 use crate::model::ActorId;
 use crate::storage::MetaStore;
 use crate::stream::build_actor_connector_splits;
-use crate::MetaResult;
+use crate::{MetaError, MetaResult};
+
+/// Which step of [`GlobalBarrierManager::recovery`] failed on a given retry attempt, so
+/// operators can tell "waiting for a replacement node to join" from "barrier injection keeps
+/// timing out" instead of just watching retries happen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryStep {
+    MigrateActors,
+    ResetComputeNodes,
+    UpdateActors,
+    BuildActors,
+    InjectBarrier,
+}
+
+/// A point-in-time snapshot of where cluster recovery currently stands. Maintained by
+/// [`GlobalBarrierManager::recovery`], returned in-process by
+/// [`GlobalBarrierManager::recovery_status`] (no meta RPC serves it) and mirrored by the
+/// Prometheus gauges/counters registered in [`recovery_metrics_registry`], so an operator
+/// watching a cluster stuck retrying recovery sees more than scattered `error!` log lines.
+#[derive(Debug, Clone, Default)]
+pub struct RecoveryStatus {
+    /// 1-indexed attempt number of the current recovery run; `0` before recovery has started.
+    pub attempt: u64,
+    /// Which sub-step failed on the most recent attempt, if any.
+    pub last_failed_step: Option<RecoveryStep>,
+    /// The stringified error from the most recent failure, if any.
+    pub last_error: Option<String>,
+    /// When the most recent failure happened.
+    pub last_failure_at: Option<Instant>,
+    /// The computed delay before the next retry, as drawn from the backoff iterator.
+    pub next_retry_delay: Option<Duration>,
+    /// Workers that are expired (awaiting a replacement) or currently being migrated away from.
+    pub expired_or_migrating_workers: Vec<WorkerId>,
+}
+
+/// Backing storage for [`GlobalBarrierManager::recovery_status`]. `GlobalBarrierManager` is a
+/// process-wide singleton (exactly one instance drives barriers for a given meta leader), so a
+/// lazily-initialized static plays the same role as an instance field without requiring a
+/// change to the struct definition in `barrier/mod.rs`.
+fn recovery_status_cell() -> &'static RwLock<RecoveryStatus> {
+    static STATUS: OnceLock<RwLock<RecoveryStatus>> = OnceLock::new();
+    STATUS.get_or_init(|| RwLock::new(RecoveryStatus::default()))
+}
+
+/// The Prometheus registry recovery metrics are registered into. A dedicated `Registry` rather
+/// than `prometheus::default_registry()`, so that wiring it into meta's real metrics endpoint
+/// (once that endpoint is reachable from this crate slice) is a matter of gathering from this
+/// registry rather than depending on every exporter in the process sharing the global default —
+/// gauges registered into the default registry don't show up in an exporter that only gathers
+/// its own `Registry` instance, which is the usual shape for a service's metrics endpoint.
+pub fn recovery_metrics_registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(Registry::new)
+}
+
+/// Registers `metric` into [`recovery_metrics_registry`], logging (rather than panicking) if
+/// registration fails — e.g. a duplicate name from a second call in the same process — so a
+/// metrics wiring mistake degrades to "this gauge isn't exported" instead of taking recovery
+/// down with it. The metric keeps working locally either way; only the export is affected.
+fn register_metric<M: prometheus::core::Collector + Clone + 'static>(name: &str, metric: M) -> M {
+    if let Err(err) = recovery_metrics_registry().register(Box::new(metric.clone())) {
+        warn!(metric = name, error = %err, "failed to register recovery metric with the registry");
+    }
+    metric
+}
+
+/// Prometheus gauges/counters mirroring [`RecoveryStatus`], updated every time recovery's
+/// status changes so an operator can alert on them directly instead of polling
+/// [`GlobalBarrierManager::recovery_status`].
+struct RecoveryMetrics {
+    attempt: IntGauge,
+    attempts_total: IntCounter,
+    failures_total: IntCounter,
+    last_failure_unix_secs: IntGauge,
+}
+
+impl RecoveryMetrics {
+    fn get() -> &'static RecoveryMetrics {
+        static METRICS: OnceLock<RecoveryMetrics> = OnceLock::new();
+        METRICS.get_or_init(|| RecoveryMetrics {
+            attempt: register_metric(
+                "meta_recovery_attempt",
+                IntGauge::with_opts(Opts::new(
+                    "meta_recovery_attempt",
+                    "1-indexed attempt number of the recovery run currently in progress, or 0 \
+                     if recovery isn't running",
+                ))
+                .expect("static metric spec is valid"),
+            ),
+            attempts_total: register_metric(
+                "meta_recovery_attempts_total",
+                IntCounter::with_opts(Opts::new(
+                    "meta_recovery_attempts_total",
+                    "Total number of recovery attempts made since this meta node became leader",
+                ))
+                .expect("static metric spec is valid"),
+            ),
+            failures_total: register_metric(
+                "meta_recovery_failures_total",
+                IntCounter::with_opts(Opts::new(
+                    "meta_recovery_failures_total",
+                    "Total number of recovery attempts that failed and were retried",
+                ))
+                .expect("static metric spec is valid"),
+            ),
+            last_failure_unix_secs: register_metric(
+                "meta_recovery_last_failure_unix_secs",
+                IntGauge::with_opts(Opts::new(
+                    "meta_recovery_last_failure_unix_secs",
+                    "Unix timestamp of the most recent recovery attempt failure, or 0 if none \
+                     yet",
+                ))
+                .expect("static metric spec is valid"),
+            ),
+        })
+    }
+}
+
+/// How [`GlobalBarrierManager::get_migrate_map_plan`] places the actors that were running on
+/// an expired worker, inspired by the tradeoffs of a cluster-rebalancing admin flow.
+#[derive(Debug, Clone, Copy)]
+pub enum MigrationPolicy {
+    /// Wait indefinitely for exactly as many brand-new compute nodes to join as there were
+    /// expired workers, then map each expired worker 1:1 onto a fresh node. The historical
+    /// behavior.
+    WaitForReplacement,
+    /// Wait for replacements up to [`GlobalBarrierManager::REDISTRIBUTE_WAIT_TIMEOUT`], then
+    /// spread any still-orphaned actors across the currently-running compute nodes with
+    /// least-loaded bin-packing, as long as at least `min_nodes` of them are up. If fewer than
+    /// `min_nodes` nodes survive, keeps waiting for replacements instead.
+    RedistributeToSurvivors { min_nodes: usize },
+}
 
 impl<S> GlobalBarrierManager<S>
 where
@@ -47,6 +182,17 @@ where
     const RECOVERY_RETRY_BASE_INTERVAL: u64 = 20;
     // Retry max interval.
     const RECOVERY_RETRY_MAX_INTERVAL: Duration = Duration::from_secs(5);
+    // How long `get_migrate_map_plan` waits for replacement nodes before redistributing
+    // orphaned actors across survivors under `MigrationPolicy::RedistributeToSurvivors`.
+    const REDISTRIBUTE_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+    // Default policy used by `migrate_actors` when the cluster has enough surviving capacity
+    // to self-heal rather than block recovery on a replacement node showing up.
+    const DEFAULT_MIGRATION_POLICY: MigrationPolicy =
+        MigrationPolicy::RedistributeToSurvivors { min_nodes: 1 };
+    // Caps the number of concurrent per-node RPCs issued by `update_actors`/`build_actors` so
+    // a very large cluster doesn't open thousands of in-flight streaming-service connections
+    // at once.
+    const MAX_CONCURRENT_NODE_RPCS: usize = 64;
 
     #[inline(always)]
     /// Initialize a retry strategy for operation in recovery.
@@ -121,87 +267,134 @@ where
         self.clean_dirty_fragments()
             .await
             .expect("clean dirty fragments");
-        let retry_strategy = Self::get_retry_strategy();
-        let (new_epoch, _responses) = tokio_retry::Retry::spawn(retry_strategy, || async {
-            let mut info = self.resolve_actor_info_for_recovery().await;
-            let mut new_epoch = prev_epoch.next();
-
-            // Migrate actors in expired CN to newly joined one.
-            let migrated = self.migrate_actors(&info).await.inspect_err(|err| {
-                error!(err = ?err, "migrate actors failed");
-            })?;
-            if migrated {
-                info = self.resolve_actor_info_for_recovery().await;
-            }
 
-            // Reset all compute nodes, stop and drop existing actors.
-            self.reset_compute_nodes(&info).await.inspect_err(|err| {
-                error!(err = ?err, "reset compute nodes failed");
-            })?;
-
-            // update and build all actors.
-            self.update_actors(&info).await.inspect_err(|err| {
-                error!(err = ?err, "update actors failed");
-            })?;
-            self.build_actors(&info).await.inspect_err(|err| {
-                error!(err = ?err, "build_actors failed");
-            })?;
-
-            // get split assignments for all actors
-            let source_split_assignments = self.source_manager.list_assignments().await;
-            let command = Command::Plain(Some(Mutation::Add(AddMutation {
-                actor_dispatchers: Default::default(),
-                actor_splits: build_actor_connector_splits(&source_split_assignments),
-            })));
-
-            let prev_epoch = new_epoch;
-            new_epoch = prev_epoch.next();
-            // checkpoint, used as init barrier to initialize all executors.
-            let command_ctx = Arc::new(CommandContext::new(
-                self.fragment_manager.clone(),
-                self.snapshot_manager.clone(),
-                self.env.stream_client_pool_ref(),
-                info,
-                prev_epoch,
-                new_epoch,
-                command,
-                true,
-                self.source_manager.clone(),
-            ));
-
-            let (barrier_complete_tx, mut barrier_complete_rx) =
-                tokio::sync::mpsc::unbounded_channel();
-            self.inject_barrier(command_ctx.clone(), barrier_complete_tx)
-                .await;
-            match barrier_complete_rx.recv().await.unwrap() {
-                (_, Ok(response)) => {
-                    if let Err(err) = command_ctx.post_collect().await {
-                        error!(err = ?err, "post_collect failed");
-                        return Err(err);
+        *recovery_status_cell().write().await = RecoveryStatus::default();
+        RecoveryMetrics::get().attempt.set(0);
+
+        let mut retry_delays = Self::get_retry_strategy();
+        let mut attempt: u64 = 0;
+        let new_epoch = loop {
+            attempt += 1;
+            recovery_status_cell().write().await.attempt = attempt;
+            let metrics = RecoveryMetrics::get();
+            metrics.attempt.set(attempt as i64);
+            metrics.attempts_total.inc();
+
+            match self.recover_once(prev_epoch).await {
+                Ok(new_epoch) => break new_epoch,
+                Err((step, err)) => {
+                    error!(err = ?err, step = ?step, attempt, "recovery attempt failed");
+                    let delay = retry_delays
+                        .next()
+                        .unwrap_or(Self::RECOVERY_RETRY_MAX_INTERVAL);
+                    {
+                        let mut status = recovery_status_cell().write().await;
+                        status.last_failed_step = Some(step);
+                        status.last_error = Some(err.to_string());
+                        status.last_failure_at = Some(Instant::now());
+                        status.next_retry_delay = Some(delay);
                     }
-                    Ok((new_epoch, response))
-                }
-                (_, Err(err)) => {
-                    error!(err = ?err, "inject_barrier failed");
-                    Err(err)
+                    metrics.failures_total.inc();
+                    let now_unix_secs = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |d| d.as_secs());
+                    metrics.last_failure_unix_secs.set(now_unix_secs as i64);
+                    tokio::time::sleep(delay).await;
                 }
             }
-        })
-        .await
-        .expect("Retry until recovery success.");
+        };
+        RecoveryMetrics::get().attempt.set(0);
         tracing::info!("recovery success");
 
         new_epoch
     }
 
+    /// Returns a snapshot of the current recovery progress. Meant to back a meta RPC so
+    /// operators can observe recovery instead of just watching retries happen: the
+    /// `risingwave_pb` meta service definition this crate slice has access to has no method for
+    /// it yet (that's a proto-level change outside this file), so for now this is reachable only
+    /// in-process; the matching Prometheus gauges/counters are registered into
+    /// [`recovery_metrics_registry`] regardless, independently of whether anything calls this
+    /// method.
+    pub async fn recovery_status(&self) -> RecoveryStatus {
+        recovery_status_cell().read().await.clone()
+    }
+
+    /// Runs a single recovery attempt, tagging any failure with the [`RecoveryStep`] it
+    /// occurred in so [`Self::recovery`] can record it in [`RecoveryStatus`].
+    async fn recover_once(&self, prev_epoch: Epoch) -> Result<Epoch, (RecoveryStep, MetaError)> {
+        let mut info = self.resolve_actor_info_for_recovery().await;
+        let mut new_epoch = prev_epoch.next();
+
+        // Migrate actors in expired CN to newly joined one.
+        let migrated_actors = self
+            .migrate_actors(&info)
+            .await
+            .map_err(|err| (RecoveryStep::MigrateActors, err))?;
+        if migrated_actors.is_some() {
+            info = self.resolve_actor_info_for_recovery().await;
+        }
+
+        // Reset compute nodes, stopping and dropping all actors.
+        self.reset_compute_nodes(&info)
+            .await
+            .map_err(|err| (RecoveryStep::ResetComputeNodes, err))?;
+
+        // update and build the actors.
+        self.update_actors(&info)
+            .await
+            .map_err(|err| (RecoveryStep::UpdateActors, err))?;
+        self.build_actors(&info)
+            .await
+            .map_err(|err| (RecoveryStep::BuildActors, err))?;
+
+        let source_split_assignments = self.source_manager.list_assignments().await;
+        let command = Command::Plain(Some(Mutation::Add(AddMutation {
+            actor_dispatchers: Default::default(),
+            actor_splits: build_actor_connector_splits(&source_split_assignments),
+        })));
+
+        let prev_epoch = new_epoch;
+        new_epoch = prev_epoch.next();
+        // checkpoint, used as init barrier to initialize all executors.
+        let command_ctx = Arc::new(CommandContext::new(
+            self.fragment_manager.clone(),
+            self.snapshot_manager.clone(),
+            self.env.stream_client_pool_ref(),
+            info,
+            prev_epoch,
+            new_epoch,
+            command,
+            true,
+            self.source_manager.clone(),
+        ));
+
+        let (barrier_complete_tx, mut barrier_complete_rx) =
+            tokio::sync::mpsc::unbounded_channel();
+        self.inject_barrier(command_ctx.clone(), barrier_complete_tx)
+            .await;
+        match barrier_complete_rx.recv().await.unwrap() {
+            (_, Ok(_response)) => {
+                command_ctx
+                    .post_collect()
+                    .await
+                    .map_err(|err| (RecoveryStep::InjectBarrier, err))?;
+                Ok(new_epoch)
+            }
+            (_, Err(err)) => Err((RecoveryStep::InjectBarrier, err)),
+        }
+    }
+
     /// map expired CNs to newly joined CNs, so we can migrate actors later
-    /// wait until get a sufficient amount of new CNs
+    /// wait until get a sufficient amount of new CNs, unless `policy` allows redistributing
+    /// onto survivors instead
     /// return "map of `ActorId` in expired CN to new CN id" and "map of `WorkerId` to
     /// `WorkerNode` struct in new CNs"
     async fn get_migrate_map_plan(
         &self,
         info: &BarrierActorInfo,
         expired_workers: &[WorkerId],
+        policy: MigrationPolicy,
     ) -> (HashMap<ActorId, WorkerId>, HashMap<WorkerId, WorkerNode>) {
         let mut cur = 0;
         let mut migrate_map = HashMap::new();
@@ -213,10 +406,11 @@ where
                 .list_worker_node(WorkerType::ComputeNode, Some(State::Running))
                 .await;
             let new_nodes = current_nodes
-                .into_iter()
+                .iter()
                 .filter(|node| {
                     !info.actor_map.contains_key(&node.id) && !node_map.contains_key(&node.id)
                 })
+                .cloned()
                 .collect_vec();
             for new_node in new_nodes {
                 let actors = info.actor_map.get(&expired_workers[cur]).unwrap();
@@ -235,6 +429,33 @@ where
                     return (migrate_map, node_map);
                 }
             }
+
+            if let MigrationPolicy::RedistributeToSurvivors { min_nodes } = policy {
+                if start.elapsed() >= Self::REDISTRIBUTE_WAIT_TIMEOUT {
+                    if current_nodes.len() >= min_nodes {
+                        debug!(
+                            "no replacement after {}s, redistributing {} orphaned actors across {} surviving nodes",
+                            start.elapsed().as_secs(),
+                            expired_workers.len() - cur,
+                            current_nodes.len()
+                        );
+                        self.redistribute_orphaned_actors(
+                            info,
+                            &expired_workers[cur..],
+                            &current_nodes,
+                            &mut migrate_map,
+                            &mut node_map,
+                        );
+                        return (migrate_map, node_map);
+                    }
+                    warn!(
+                        "only {} surviving nodes, need at least {} to redistribute; continuing to wait for replacements",
+                        current_nodes.len(),
+                        min_nodes
+                    );
+                }
+            }
+
             warn!(
                 "waiting for new worker to join, elapsed: {}s",
                 start.elapsed().as_secs()
@@ -245,7 +466,53 @@ where
         (migrate_map, node_map)
     }
 
-    async fn migrate_actors(&self, info: &BarrierActorInfo) -> MetaResult<bool> {
+    /// Spreads the actors of `remaining_expired_workers` across `current_nodes` using
+    /// least-loaded bin-packing: each orphaned actor goes to the running node with the fewest
+    /// actors assigned so far (counting both its pre-existing actors and ones already
+    /// redistributed in this call), breaking ties by worker id for determinism.
+    fn redistribute_orphaned_actors(
+        &self,
+        info: &BarrierActorInfo,
+        remaining_expired_workers: &[WorkerId],
+        current_nodes: &[WorkerNode],
+        migrate_map: &mut HashMap<ActorId, WorkerId>,
+        node_map: &mut HashMap<WorkerId, WorkerNode>,
+    ) {
+        let mut load: BTreeMap<WorkerId, usize> = current_nodes
+            .iter()
+            .map(|node| (node.id, info.actor_map.get(&node.id).map_or(0, Vec::len)))
+            .collect();
+
+        for &expired_worker in remaining_expired_workers {
+            let Some(actors) = info.actor_map.get(&expired_worker) else {
+                continue;
+            };
+            for &actor_id in actors {
+                let target = *load
+                    .iter()
+                    .min_by_key(|&(&worker_id, &count)| (count, worker_id))
+                    .map(|(worker_id, _)| worker_id)
+                    .expect("redistribution requires at least one surviving node");
+
+                migrate_map.insert(actor_id, target);
+                *load.get_mut(&target).unwrap() += 1;
+                node_map.entry(target).or_insert_with(|| {
+                    current_nodes
+                        .iter()
+                        .find(|node| node.id == target)
+                        .unwrap()
+                        .clone()
+                });
+            }
+        }
+    }
+
+    /// Returns `None` if there were no expired workers to migrate away from. Otherwise returns
+    /// `Some` of the actor-to-new-worker map that was just applied.
+    async fn migrate_actors(
+        &self,
+        info: &BarrierActorInfo,
+    ) -> MetaResult<Option<HashMap<ActorId, WorkerId>>> {
         debug!("start migrate actors.");
 
         // 1. get expired workers
@@ -257,21 +524,34 @@ where
             .collect_vec();
         if expired_workers.is_empty() {
             debug!("no expired workers, skipping.");
-            return Ok(false);
+            recovery_status_cell()
+                .write()
+                .await
+                .expired_or_migrating_workers
+                .clear();
+            return Ok(None);
         }
         debug!("got expired workers {:#?}", expired_workers);
+        recovery_status_cell()
+            .write()
+            .await
+            .expired_or_migrating_workers = expired_workers.clone();
 
-        let (migrate_map, node_map) = self.get_migrate_map_plan(info, &expired_workers).await;
+        let (migrate_map, node_map) = self
+            .get_migrate_map_plan(info, &expired_workers, Self::DEFAULT_MIGRATION_POLICY)
+            .await;
         // 2. migrate actors in fragments
         self.fragment_manager
             .migrate_actors(&migrate_map, &node_map)
             .await?;
         debug!("migrate actors succeed.");
 
-        Ok(true)
+        Ok(Some(migrate_map))
     }
 
-    /// Update all actors in compute nodes.
+    /// Update every actor on its compute node. Every node is sent `broadcast_actor_info_table`
+    /// before its own `update_actors`, but different nodes progress independently, bounded by
+    /// [`Self::MAX_CONCURRENT_NODE_RPCS`] in-flight at once.
     async fn update_actors(&self, info: &BarrierActorInfo) -> MetaResult<()> {
         let mut actor_infos = vec![];
         for (node_id, actors) in &info.actor_map {
@@ -288,33 +568,42 @@ where
         }
 
         let node_actors = self.fragment_manager.all_node_actors(false).await;
-        for (node_id, actors) in &info.actor_map {
-            let node = info.node_map.get(node_id).unwrap();
-            let client = self.env.stream_client_pool().get(node).await?;
-
-            client
-                .broadcast_actor_info_table(BroadcastActorInfoTableRequest {
-                    info: actor_infos.clone(),
-                })
-                .await?;
+        let futures = info.actor_map.iter().map(|(node_id, actors)| {
+            let actor_infos = actor_infos.clone();
+            let node_actors = node_actors.get(node_id).cloned().unwrap_or_default();
+
+            async move {
+                let node = info.node_map.get(node_id).unwrap();
+                let client = self.env.stream_client_pool().get(node).await?;
+
+                client
+                    .broadcast_actor_info_table(BroadcastActorInfoTableRequest { info: actor_infos })
+                    .await?;
+
+                let request_id = Uuid::new_v4().to_string();
+                tracing::debug!(request_id = request_id.as_str(), actors = ?actors, "update actors");
+                client
+                    .update_actors(UpdateActorsRequest {
+                        request_id,
+                        actors: node_actors,
+                        ..Default::default()
+                    })
+                    .await
+            }
+        });
 
-            let request_id = Uuid::new_v4().to_string();
-            tracing::debug!(request_id = request_id.as_str(), actors = ?actors, "update actors");
-            client
-                .update_actors(UpdateActorsRequest {
-                    request_id,
-                    actors: node_actors.get(node_id).cloned().unwrap_or_default(),
-                    ..Default::default()
-                })
-                .await?;
-        }
+        stream::iter(futures)
+            .buffer_unordered(Self::MAX_CONCURRENT_NODE_RPCS)
+            .try_for_each(|_| async { Ok(()) })
+            .await?;
 
         Ok(())
     }
 
-    /// Build all actors in compute nodes.
+    /// Build every actor on its compute node, fanning out to all nodes concurrently (bounded by
+    /// [`Self::MAX_CONCURRENT_NODE_RPCS`]) instead of one at a time.
     async fn build_actors(&self, info: &BarrierActorInfo) -> MetaResult<()> {
-        for (node_id, actors) in &info.actor_map {
+        let futures = info.actor_map.iter().map(|(node_id, actors)| async move {
             let node = info.node_map.get(node_id).unwrap();
             let client = self.env.stream_client_pool().get(node).await?;
 
@@ -325,17 +614,23 @@ where
                     request_id,
                     actor_id: actors.to_owned(),
                 })
-                .await?;
-        }
+                .await
+        });
+
+        stream::iter(futures)
+            .buffer_unordered(Self::MAX_CONCURRENT_NODE_RPCS)
+            .try_for_each(|_| async { Ok(()) })
+            .await?;
 
         Ok(())
     }
 
-    /// Reset all compute nodes by calling `force_stop_actors`.
+    /// Reset every compute node hosting an actor by calling `force_stop_actors`.
     async fn reset_compute_nodes(&self, info: &BarrierActorInfo) -> MetaResult<()> {
-        let futures = info.node_map.values().map(|worker_node| async move {
+        let futures = info.actor_map.iter().map(|(node_id, actors)| async move {
+            let worker_node = info.node_map.get(node_id).unwrap();
             let client = self.env.stream_client_pool().get(worker_node).await?;
-            debug!(worker = ?worker_node.id, "force stop actors");
+            debug!(worker = ?worker_node.id, actors = ?actors, "force stop actors");
             client
                 .force_stop_actors(ForceStopActorsRequest {
                     request_id: Uuid::new_v4().to_string(),
@@ -348,4 +643,5 @@ where
 
         Ok(())
     }
+
 }
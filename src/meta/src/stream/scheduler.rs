@@ -12,15 +12,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::{BTreeMap, HashMap, LinkedList};
+use std::collections::{BTreeMap, HashMap, HashSet, LinkedList};
 use std::iter::empty;
 
-use anyhow::{anyhow, Context};
+use anyhow::anyhow;
 use itertools::Itertools;
-use rand::prelude::SliceRandom;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use risingwave_common::bail;
-use risingwave_common::buffer::Bitmap;
-use risingwave_common::hash::ParallelUnitMapping;
+use risingwave_common::buffer::{Bitmap, BitmapBuilder};
+use risingwave_common::hash::{ParallelUnitMapping, VirtualNode};
 use risingwave_pb::common::{ActorInfo, Buffer, ParallelUnit, WorkerNode};
 use risingwave_pb::meta::table_fragments::fragment::FragmentDistributionType;
 use risingwave_pb::meta::table_fragments::Fragment;
@@ -29,13 +30,29 @@ use crate::manager::{WorkerId, WorkerLocations};
 use crate::model::ActorId;
 use crate::MetaResult;
 
+/// Default zone tag used for workers that don't report one, so that a cluster with no zone
+/// information configured behaves exactly like before (a single zone, i.e. no redundancy
+/// constraint can ever be satisfied beyond `zone_redundancy = 1`).
+const DEFAULT_ZONE: &str = "default";
+
 /// [`Scheduler`] defines schedule logic for mv actors.
 pub struct Scheduler {
     /// The parallel units of the cluster in a round-robin manner on each worker.
     all_parallel_units: Vec<ParallelUnit>,
+    /// Zone (e.g. rack/datacenter) each worker belongs to. Workers absent from this map are
+    /// treated as if they were all in [`DEFAULT_ZONE`].
+    worker_zones: HashMap<WorkerId, String>,
+    /// The minimum number of distinct zones the parallel units chosen for a single fragment
+    /// should span, when the cluster has enough zones to make that possible.
+    zone_redundancy: usize,
+    /// Capacity weight (e.g. relative core count) of each worker, used to bias selection
+    /// towards heavier workers. Workers absent from this map default to a weight of `1.0`,
+    /// matching today's uniform behavior.
+    worker_weights: HashMap<WorkerId, f64>,
 }
 
 /// [`ScheduledLocations`] represents the location of scheduled result.
+#[derive(Clone)]
 pub struct ScheduledLocations {
     /// actor location map.
     pub actor_locations: BTreeMap<ActorId, ParallelUnit>,
@@ -131,9 +148,68 @@ impl ScheduledLocations {
 
 impl Scheduler {
     pub fn new(parallel_units: impl IntoIterator<Item = ParallelUnit>) -> Self {
-        // Group parallel units with worker node.
+        Self::with_zones(parallel_units, HashMap::new(), 1)
+    }
+
+    /// Like [`Scheduler::new`], but additionally takes a `worker_zones` mapping (failure-domain
+    /// tag per worker, e.g. rack or datacenter) and a `zone_redundancy`: the schedule procedure
+    /// will try to spread a fragment's parallel units across at least that many distinct zones,
+    /// falling back to best-effort spreading when the cluster doesn't have enough zones.
+    ///
+    /// Nothing in this crate builds a `worker_zones` map yet: `risingwave_pb::common::WorkerNode`
+    /// has no zone/rack field to read it from, so there's no real data source to populate this
+    /// with outside of tests. [`Scheduler::new`] remains the only constructor any real caller
+    /// uses; call this directly once a zone tag is plumbed onto `WorkerNode` and `ClusterManager`
+    /// exposes it, rather than leaving callers to assemble the map by hand.
+    pub fn with_zones(
+        parallel_units: impl IntoIterator<Item = ParallelUnit>,
+        worker_zones: HashMap<WorkerId, String>,
+        zone_redundancy: usize,
+    ) -> Self {
+        Self::with_zones_and_weights(parallel_units, worker_zones, zone_redundancy, HashMap::new())
+    }
+
+    /// Like [`Scheduler::with_zones`], but additionally takes a `worker_weights` mapping
+    /// (relative capacity, e.g. core count, per worker) used to bias parallel-unit selection so
+    /// heavier workers receive proportionally more actors. Workers absent from the map default
+    /// to a weight of `1.0`.
+    pub fn with_zones_and_weights(
+        parallel_units: impl IntoIterator<Item = ParallelUnit>,
+        worker_zones: HashMap<WorkerId, String>,
+        zone_redundancy: usize,
+        worker_weights: HashMap<WorkerId, f64>,
+    ) -> Self {
+        Self::with_params(
+            parallel_units,
+            worker_zones,
+            zone_redundancy,
+            worker_weights,
+            HashSet::new(),
+        )
+    }
+
+    /// Like [`Scheduler::with_zones_and_weights`], but additionally takes a `draining_workers`
+    /// set: parallel units belonging to a draining worker are excluded from
+    /// `all_parallel_units` entirely, so new placement (and rescheduling) never lands on a
+    /// worker that's being decommissioned. Use [`Scheduler::reschedule_off`] to move actors that
+    /// are already on a newly-draining worker elsewhere.
+    ///
+    /// As with `worker_zones` (see [`Scheduler::with_zones`]), `worker_weights` and
+    /// `draining_workers` have no real-caller data source in this crate today; they're exercised
+    /// only by this file's own tests until `ClusterManager` grows a way to surface them.
+    pub fn with_params(
+        parallel_units: impl IntoIterator<Item = ParallelUnit>,
+        worker_zones: HashMap<WorkerId, String>,
+        zone_redundancy: usize,
+        worker_weights: HashMap<WorkerId, f64>,
+        draining_workers: HashSet<WorkerId>,
+    ) -> Self {
+        // Group parallel units with worker node, skipping workers that are draining.
         let mut parallel_units_map = BTreeMap::new();
         for p in parallel_units {
+            if draining_workers.contains(&p.worker_node_id) {
+                continue;
+            }
             parallel_units_map
                 .entry(p.worker_node_id)
                 .or_insert_with(Vec::new)
@@ -159,13 +235,118 @@ impl Scheduler {
 
         Self {
             all_parallel_units: round_robin,
+            worker_zones,
+            zone_redundancy: zone_redundancy.max(1),
+            worker_weights,
         }
     }
 
+    /// Returns the zone tag of the worker owning `parallel_unit`, defaulting to
+    /// [`DEFAULT_ZONE`] when the worker didn't report one.
+    fn zone_of(&self, parallel_unit: &ParallelUnit) -> &str {
+        self.worker_zones
+            .get(&parallel_unit.worker_node_id)
+            .map(String::as_str)
+            .unwrap_or(DEFAULT_ZONE)
+    }
+
+    /// Returns the capacity weight of the worker owning `parallel_unit`, defaulting to `1.0`.
+    fn weight_of(&self, parallel_unit: &ParallelUnit) -> f64 {
+        self.worker_weights
+            .get(&parallel_unit.worker_node_id)
+            .copied()
+            .unwrap_or(1.0)
+    }
+
+    /// Returns a weighted random permutation of `all_parallel_units`, using the
+    /// Efraimidis–Spirakis weighted reservoir-sampling trick: each unit `i` with weight `w_i`
+    /// draws `u_i ~ Uniform(0, 1)` and is assigned key `k_i = -ln(u_i) / w_i`; sorting ascending
+    /// by key yields a permutation where the probability of a unit being drawn first is
+    /// proportional to its weight. The RNG is seeded from `seed` (the fragment id) so the
+    /// permutation, and hence scheduling, is reproducible.
+    fn weighted_shuffle(&self, seed: u64) -> Vec<ParallelUnit> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        self.all_parallel_units
+            .iter()
+            .cloned()
+            .map(|p| {
+                let u: f64 = rng.gen_range(f64::EPSILON..1.0);
+                let key = -u.ln() / self.weight_of(&p);
+                (key, p)
+            })
+            .sorted_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap())
+            .map(|(_, p)| p)
+            .collect()
+    }
+
+    /// Returns the parallel units to draw a schedule from, in priority order. When
+    /// `worker_weights` actually carries real per-worker weights, this is the capacity-weighted
+    /// shuffle (seeded from `seed`, typically the fragment id, so the choice is reproducible).
+    /// With the default empty map — i.e. every worker implicitly weight `1.0`, since nothing in
+    /// this crate populates `worker_weights` yet (see [`Scheduler::with_params`]) — a uniformly
+    /// random shuffle would no longer spread a fragment's actors round-robin across workers the
+    /// way a plain prefix of `all_parallel_units` does, for no weighting benefit. So in that case
+    /// this returns `all_parallel_units` unshuffled, preserving the round-robin behavior exactly.
+    fn ordered_parallel_units(&self, seed: u64) -> Vec<ParallelUnit> {
+        if self.worker_weights.is_empty() {
+            self.all_parallel_units.clone()
+        } else {
+            self.weighted_shuffle(seed)
+        }
+    }
+
+    /// Picks `count` parallel units out of [`Scheduler::ordered_parallel_units`] (seeded from
+    /// `seed`, typically the fragment id, so the choice is reproducible), preferring to spread
+    /// them across at least `zone_redundancy` distinct zones before repeating a zone. This is a
+    /// best-effort rebalancing of that order: when the cluster doesn't have enough distinct
+    /// zones, it simply degrades to the plain prefix.
+    fn zone_balanced_prefix(&self, count: usize, seed: u64) -> Vec<ParallelUnit> {
+        let shuffled = self.ordered_parallel_units(seed);
+
+        if self.zone_redundancy <= 1 || self.worker_zones.is_empty() {
+            return shuffled[..count].to_vec();
+        }
+
+        let mut by_zone: LinkedList<_> = {
+            let mut grouped: BTreeMap<&str, Vec<ParallelUnit>> = BTreeMap::new();
+            for p in &shuffled {
+                grouped.entry(self.zone_of(p)).or_default().push(p.clone());
+            }
+            grouped.into_values().map(|v| v.into_iter()).collect()
+        };
+
+        let mut chosen = Vec::with_capacity(count);
+        'outer: while !by_zone.is_empty() {
+            let mut exhausted = vec![];
+            for (idx, zone) in by_zone.iter_mut().enumerate() {
+                match zone.next() {
+                    Some(p) => {
+                        chosen.push(p);
+                        if chosen.len() == count {
+                            break 'outer;
+                        }
+                    }
+                    None => exhausted.push(idx),
+                }
+            }
+            let mut remaining = LinkedList::new();
+            for (idx, zone) in by_zone.into_iter().enumerate() {
+                if !exhausted.contains(&idx) {
+                    remaining.push_back(zone);
+                }
+            }
+            by_zone = remaining;
+        }
+        chosen
+    }
+
     /// Schedules input fragments to different parallel units (workers).
     /// The schedule procedure is two-fold:
     /// (1) For singleton fragments, we schedule each to one parallel unit randomly.
-    /// (2) For normal fragments, we schedule them to each worker node in a round-robin manner.
+    /// (2) For normal fragments, we schedule them to each worker node in a round-robin manner,
+    /// preferring to spread the chosen parallel units across `zone_redundancy` distinct zones
+    /// (see [`Scheduler::with_zones`]) so that the loss of a single zone doesn't take out every
+    /// parallel unit of the fragment.
     pub fn schedule(
         &self,
         fragment: &mut Fragment,
@@ -187,11 +368,13 @@ impl Scheduler {
                 // Schedule the fragment to the same parallel unit as upstream.
                 locations.schedule_colocate_with(&[colocated_actor_id.id])?
             } else {
-                // Randomly choose one parallel unit to schedule from all parallel units.
-                self.all_parallel_units
-                    .choose(&mut rand::thread_rng())
-                    .cloned()
-                    .context("no parallel unit to schedule")?
+                // Capacity-weighted random choice of one parallel unit from all parallel units
+                // (uniform when no weights are plumbed in), seeded by the fragment id so the pick
+                // is reproducible.
+                self.weighted_shuffle(fragment.fragment_id as u64)
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| anyhow!("no parallel unit to schedule"))?
             };
 
             // Build vnode mapping. However, we'll leave vnode field of actors unset for singletons.
@@ -249,10 +432,13 @@ impl Scheduler {
                 fragment.vnode_mapping =
                     Some(ParallelUnitMapping::from_bitmaps(&parallel_unit_bitmap).to_protobuf());
             } else {
-                // By taking a prefix of all parallel units, we schedule the actors round-robin-ly.
-                // Then sort them by parallel unit id to make the actor ids continuous against the
-                // parallel unit id.
-                let mut parallel_units = self.all_parallel_units[..fragment.actors.len()].to_vec();
+                // By taking a (capacity-weighted, zone-balanced) prefix of all parallel units, we
+                // schedule the actors so that heavier workers receive proportionally more actors
+                // while still preferring to spread them across `zone_redundancy` distinct zones
+                // first. Then sort them by parallel unit id to make the actor ids continuous
+                // against the parallel unit id.
+                let mut parallel_units = self
+                    .zone_balanced_prefix(fragment.actors.len(), fragment.fragment_id as u64);
                 parallel_units.sort_unstable_by_key(|p| p.id);
 
                 // Build vnode mapping according to the parallel units.
@@ -278,17 +464,504 @@ impl Scheduler {
     }
 
     /// `set_fragment_vnode_mapping` works by following steps:
-    /// 1. Build a vnode mapping according to parallel units where the fragment is scheduled.
+    /// 1. Build a vnode mapping according to parallel units where the fragment is scheduled,
+    ///    reusing the fragment's previous vnode mapping (if any) to minimize the number of
+    ///    vnodes that actually move.
     /// 2. Set the vnode mapping into the fragment.
     fn set_fragment_vnode_mapping(
         &self,
         fragment: &mut Fragment,
         parallel_units: &[ParallelUnit],
     ) -> MetaResult<ParallelUnitMapping> {
-        let vnode_mapping = ParallelUnitMapping::build(parallel_units);
+        let previous_mapping = fragment
+            .vnode_mapping
+            .as_ref()
+            .map(ParallelUnitMapping::from_protobuf);
+        let vnode_mapping =
+            self.rebalance_vnode_mapping(parallel_units, previous_mapping.as_ref());
         fragment.vnode_mapping = Some(vnode_mapping.to_protobuf());
         Ok(vnode_mapping)
     }
+
+    /// Builds a vnode mapping for `parallel_units` that is balanced according to each unit's
+    /// weight while minimizing the number of vnodes reassigned relative to `previous_mapping`
+    /// (if given).
+    ///
+    /// There's only something to minimize when there's a previous mapping to diff against *and*
+    /// more than one candidate unit (with a single unit every vnode has only one place to go
+    /// regardless of history), so those are the only cases that pay for the min-cost-flow
+    /// solver below; initial placement and singletons go straight to
+    /// [`Scheduler::balanced_vnode_mapping`] instead.
+    fn rebalance_vnode_mapping(
+        &self,
+        parallel_units: &[ParallelUnit],
+        previous_mapping: Option<&ParallelUnitMapping>,
+    ) -> ParallelUnitMapping {
+        if parallel_units.is_empty() {
+            return ParallelUnitMapping::from_bitmaps(&HashMap::new());
+        }
+
+        match previous_mapping {
+            Some(previous_mapping) if parallel_units.len() > 1 => {
+                self.rebalance_vnode_mapping_via_min_cost_flow(parallel_units, previous_mapping)
+            }
+            _ => self.balanced_vnode_mapping(parallel_units),
+        }
+    }
+
+    /// Minimizes the number of vnodes reassigned relative to `previous_mapping` while keeping
+    /// the result balanced according to each unit's weight. This mirrors Garage's flow-based
+    /// partition-assignment approach (`graph_algo.rs`): a bipartite graph of source -> vnode ->
+    /// parallel unit -> sink is solved with min-cost max-flow (source->vnode and unit->sink edges
+    /// are free and capped at 1 and at the unit's ideal vnode share respectively; a vnode->unit
+    /// edge costs 0 if it preserves that vnode's previous assignment and 1 otherwise).
+    /// Successive shortest augmenting paths simultaneously find a feasible balanced flow and
+    /// minimize its cost, so scale-up/scale-down only reassigns as many vnodes as strictly
+    /// necessary.
+    fn rebalance_vnode_mapping_via_min_cost_flow(
+        &self,
+        parallel_units: &[ParallelUnit],
+        previous_mapping: &ParallelUnitMapping,
+    ) -> ParallelUnitMapping {
+        let vnode_count = VirtualNode::COUNT;
+
+        let previous_vnode_unit: HashMap<usize, u32> = previous_mapping
+            .to_bitmaps()
+            .into_iter()
+            .flat_map(|(unit_id, bitmap)| {
+                bitmap
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, is_set)| *is_set)
+                    .map(move |(vnode, _)| (vnode, unit_id))
+            })
+            .collect();
+
+        // Node numbering: 0 = source, 1..=vnode_count = one node per vnode,
+        // unit_base..unit_base+len = one node per parallel unit, last = sink.
+        let unit_base = 1 + vnode_count;
+        let sink = unit_base + parallel_units.len();
+        let mut flow = min_cost_flow::MinCostFlow::new(sink + 1);
+
+        for vnode in 0..vnode_count {
+            flow.add_edge(0, 1 + vnode, 1, 0);
+        }
+
+        let total_weight: f64 = parallel_units.iter().map(|p| self.weight_of(p)).sum();
+        let mut vnode_unit_edges = vec![vec![0usize; parallel_units.len()]; vnode_count];
+        for (unit_idx, unit) in parallel_units.iter().enumerate() {
+            for vnode in 0..vnode_count {
+                let cost = match previous_vnode_unit.get(&vnode) {
+                    Some(&prev_unit_id) if prev_unit_id == unit.id => 0,
+                    _ => 1,
+                };
+                vnode_unit_edges[vnode][unit_idx] =
+                    flow.add_edge(1 + vnode, unit_base + unit_idx, 1, cost);
+            }
+
+            let ideal_share =
+                (vnode_count as f64 * self.weight_of(unit) / total_weight).ceil() as i64;
+            flow.add_edge(unit_base + unit_idx, sink, ideal_share.max(0), 0);
+        }
+
+        flow.solve(0, sink);
+
+        // `BitmapBuilder` only supports appending, so collect one bool slot per vnode first.
+        let mut assigned = vec![vec![false; vnode_count]; parallel_units.len()];
+        for (vnode, edges) in vnode_unit_edges.into_iter().enumerate() {
+            for (unit_idx, edge) in edges.into_iter().enumerate() {
+                if flow.flow_on(edge, 1) == 1 {
+                    assigned[unit_idx][vnode] = true;
+                    break;
+                }
+            }
+        }
+
+        let parallel_unit_bitmap: HashMap<_, _> = parallel_units
+            .iter()
+            .enumerate()
+            .map(|(unit_idx, unit)| {
+                let mut builder = BitmapBuilder::default();
+                for &is_set in &assigned[unit_idx] {
+                    builder.append(is_set);
+                }
+                (unit.id, builder.finish())
+            })
+            .collect();
+
+        ParallelUnitMapping::from_bitmaps(&parallel_unit_bitmap)
+    }
+
+    /// Directly hands out vnodes in capacity-weighted, contiguous blocks using the largest
+    /// remainder method, without running the min-cost-flow solver: there's no previous mapping
+    /// (or only one candidate unit) to minimize reassignment against, so the flow formulation
+    /// would just be solving a balance problem it can already solve directly.
+    fn balanced_vnode_mapping(&self, parallel_units: &[ParallelUnit]) -> ParallelUnitMapping {
+        let vnode_count = VirtualNode::COUNT;
+        let total_weight: f64 = parallel_units.iter().map(|p| self.weight_of(p)).sum();
+
+        let ideal_shares: Vec<f64> = parallel_units
+            .iter()
+            .map(|unit| vnode_count as f64 * self.weight_of(unit) / total_weight)
+            .collect();
+        let mut shares: Vec<usize> = ideal_shares
+            .iter()
+            .map(|share| share.floor() as usize)
+            .collect();
+
+        // Flooring each share short-changes the total by a handful of vnodes; hand those out to
+        // the units with the largest fractional remainder first so the total always sums to
+        // exactly `vnode_count`.
+        let mut by_remainder = (0..parallel_units.len()).collect_vec();
+        by_remainder.sort_by(|&a, &b| {
+            let remainder_a = ideal_shares[a] - shares[a] as f64;
+            let remainder_b = ideal_shares[b] - shares[b] as f64;
+            remainder_b.partial_cmp(&remainder_a).unwrap()
+        });
+        let shortfall = vnode_count - shares.iter().sum::<usize>();
+        for &idx in by_remainder.iter().take(shortfall) {
+            shares[idx] += 1;
+        }
+
+        let mut parallel_unit_bitmap = HashMap::new();
+        let mut vnode = 0;
+        for (unit, share) in parallel_units.iter().zip(shares) {
+            let mut builder = BitmapBuilder::default();
+            for i in 0..vnode_count {
+                builder.append(vnode <= i && i < vnode + share);
+            }
+            vnode += share;
+            parallel_unit_bitmap.insert(unit.id, builder.finish());
+        }
+
+        ParallelUnitMapping::from_bitmaps(&parallel_unit_bitmap)
+    }
+
+    /// Reassigns every actor currently placed on `worker_id` onto the least-loaded parallel
+    /// units of `self` (which must already exclude `worker_id`'s own units, see
+    /// [`Scheduler::with_params`]), weighted by each unit's capacity. Every other actor keeps
+    /// its current placement untouched, so this only moves what's strictly necessary to
+    /// evacuate the draining worker.
+    pub fn reschedule_off(
+        &self,
+        locations: &ScheduledLocations,
+        worker_id: WorkerId,
+    ) -> MetaResult<ScheduledLocations> {
+        if self.all_parallel_units.is_empty() {
+            bail!("no non-draining parallel unit available to receive evacuated actors");
+        }
+
+        // Running load (actor count / weight) per candidate unit, seeded from the actors that
+        // are already placed there and aren't moving.
+        let mut load: HashMap<u32, f64> = self
+            .all_parallel_units
+            .iter()
+            .map(|p| (p.id, 0.0))
+            .collect();
+        for parallel_unit in locations.actor_locations.values() {
+            if parallel_unit.worker_node_id != worker_id {
+                if let Some(count) = load.get_mut(&parallel_unit.id) {
+                    *count += 1.0;
+                }
+            }
+        }
+
+        let mut new_locations = locations.clone();
+        let evacuating_actors = locations
+            .actor_locations
+            .iter()
+            .filter(|(_, p)| p.worker_node_id == worker_id)
+            .map(|(actor_id, _)| *actor_id)
+            .collect_vec();
+
+        for actor_id in evacuating_actors {
+            let target = self
+                .all_parallel_units
+                .iter()
+                .min_by(|a, b| {
+                    let load_a = load[&a.id] / self.weight_of(a).max(f64::EPSILON);
+                    let load_b = load[&b.id] / self.weight_of(b).max(f64::EPSILON);
+                    load_a
+                        .partial_cmp(&load_b)
+                        .unwrap()
+                        .then_with(|| a.id.cmp(&b.id))
+                })
+                .ok_or_else(|| anyhow!("no non-draining parallel unit available"))?
+                .clone();
+
+            *load.get_mut(&target.id).unwrap() += 1.0;
+            new_locations.actor_locations.insert(actor_id, target);
+        }
+
+        Ok(new_locations)
+    }
+}
+
+/// A structured diff between the currently applied layout and a staged reschedule proposal, as
+/// produced by [`StagedSchedule::diff`].
+#[derive(Debug, Default, Clone)]
+pub struct ScheduleDiff {
+    /// Actors that would be newly placed on each worker, keyed by worker id.
+    pub actors_added: HashMap<WorkerId, Vec<ActorId>>,
+    /// Actors that would be removed from each worker, keyed by worker id.
+    pub actors_removed: HashMap<WorkerId, Vec<ActorId>>,
+    /// Number of actors whose owning parallel unit (and hence vnode range) would change.
+    pub vnodes_moved: usize,
+    /// A rough estimate of the state-migration cost of applying this diff: one unit of cost per
+    /// moved vnode range, plus one per actor that would additionally move to a different worker
+    /// (cross-node actor migrations are pricier than an in-place vnode handoff).
+    pub estimated_migration_cost: usize,
+}
+
+/// Two-phase staged rescheduling, modeled after Garage's staged-layout design: a proposed
+/// [`ScheduledLocations`] accumulates in a buffer where it can be inspected with
+/// [`StagedSchedule::diff`] before anything actually moves, and is only applied to the "live"
+/// layout once [`StagedSchedule::commit`]'d - or discarded wholesale with
+/// [`StagedSchedule::revert`].
+pub struct StagedSchedule {
+    /// Monotonically increasing version of the currently applied layout, bumped on every
+    /// successful [`StagedSchedule::commit`].
+    layout_version: u64,
+    /// The layout currently considered live.
+    applied: ScheduledLocations,
+    /// A proposed layout staged for review, not yet applied.
+    staged: Option<ScheduledLocations>,
+}
+
+impl StagedSchedule {
+    pub fn new(applied: ScheduledLocations) -> Self {
+        Self {
+            layout_version: 0,
+            applied,
+            staged: None,
+        }
+    }
+
+    pub fn layout_version(&self) -> u64 {
+        self.layout_version
+    }
+
+    pub fn applied(&self) -> &ScheduledLocations {
+        &self.applied
+    }
+
+    pub fn staged(&self) -> Option<&ScheduledLocations> {
+        self.staged.as_ref()
+    }
+
+    /// Buffers `proposed` as the pending reschedule, replacing any previously staged proposal.
+    pub fn stage(&mut self, proposed: ScheduledLocations) {
+        self.staged = Some(proposed);
+    }
+
+    /// Computes a structured diff between the currently applied layout and the staged proposal,
+    /// or `None` if nothing is staged.
+    pub fn diff(&self) -> Option<ScheduleDiff> {
+        let staged = self.staged.as_ref()?;
+        let mut diff = ScheduleDiff::default();
+
+        let applied_workers = self.applied.worker_actors();
+        let staged_workers = staged.worker_actors();
+
+        for (worker_id, actors) in &staged_workers {
+            let previous: HashSet<_> = applied_workers
+                .get(worker_id)
+                .into_iter()
+                .flatten()
+                .copied()
+                .collect();
+            let added = actors
+                .iter()
+                .copied()
+                .filter(|a| !previous.contains(a))
+                .collect_vec();
+            if !added.is_empty() {
+                diff.actors_added.insert(*worker_id, added);
+            }
+        }
+        for (worker_id, actors) in &applied_workers {
+            let current: HashSet<_> = staged_workers
+                .get(worker_id)
+                .into_iter()
+                .flatten()
+                .copied()
+                .collect();
+            let removed = actors
+                .iter()
+                .copied()
+                .filter(|a| !current.contains(a))
+                .collect_vec();
+            if !removed.is_empty() {
+                diff.actors_removed.insert(*worker_id, removed);
+            }
+        }
+
+        // An actor present in both layouts but on a different parallel unit means its vnode
+        // range moved, even if it stayed on the same worker.
+        for (actor_id, staged_unit) in &staged.actor_locations {
+            if let Some(applied_unit) = self.applied.actor_locations.get(actor_id) {
+                if applied_unit.id != staged_unit.id {
+                    diff.vnodes_moved += 1;
+                }
+            }
+        }
+
+        let cross_worker_actors = diff
+            .actors_added
+            .values()
+            .flatten()
+            .filter(|actor_id| self.applied.actor_locations.contains_key(actor_id))
+            .count();
+        diff.estimated_migration_cost = diff.vnodes_moved + cross_worker_actors;
+
+        Some(diff)
+    }
+
+    /// Applies the staged proposal as the new live layout and bumps [`Self::layout_version`],
+    /// returning the new version. Does nothing (and returns the unchanged version) if nothing
+    /// was staged.
+    pub fn commit(&mut self) -> u64 {
+        if let Some(staged) = self.staged.take() {
+            self.applied = staged;
+            self.layout_version += 1;
+        }
+        self.layout_version
+    }
+
+    /// Discards the staged proposal without applying it.
+    pub fn revert(&mut self) {
+        self.staged = None;
+    }
+}
+
+/// A tiny min-cost max-flow solver, used by [`Scheduler::rebalance_vnode_mapping`] to compute a
+/// vnode-to-parallel-unit assignment that is both balanced and minimizes reassignment relative to
+/// a previous mapping.
+///
+/// Successive shortest augmenting paths, same as a textbook Bellman-Ford MCMF, but each
+/// augmenting path is found with Dijkstra over Johnson-reduced costs (a potential per node,
+/// updated after every augmentation) instead of a full Bellman-Ford relaxation. For the roughly
+/// `256 * parallel_units` edge graph `rebalance_vnode_mapping` builds, Bellman-Ford's O(V*E) per
+/// augmentation made every call effectively O(256 * V * E); Dijkstra with a binary heap brings
+/// each augmentation down to O(E log V), which is what makes running this on every
+/// `set_fragment_vnode_mapping` call viable. All edges this module is actually used with start
+/// out non-negative cost, so the first Dijkstra pass (potentials all zero) is already valid;
+/// standard Johnson re-weighting keeps every later pass's reduced costs non-negative too.
+mod min_cost_flow {
+    use std::cmp::Reverse;
+    use std::collections::BinaryHeap;
+
+    #[derive(Clone, Copy)]
+    struct Edge {
+        to: usize,
+        cap: i64,
+        cost: i64,
+    }
+
+    pub struct MinCostFlow {
+        num_nodes: usize,
+        edges: Vec<Edge>,
+        adj: Vec<Vec<usize>>,
+    }
+
+    impl MinCostFlow {
+        pub fn new(num_nodes: usize) -> Self {
+            Self {
+                num_nodes,
+                edges: Vec::new(),
+                adj: vec![Vec::new(); num_nodes],
+            }
+        }
+
+        /// Adds a directed edge `from -> to` with the given capacity and cost (plus its implicit
+        /// zero-capacity reverse edge), returning the index to pass to [`Self::flow_on`] later.
+        pub fn add_edge(&mut self, from: usize, to: usize, cap: i64, cost: i64) -> usize {
+            let forward = self.edges.len();
+            self.edges.push(Edge { to, cap, cost });
+            self.adj[from].push(forward);
+            let backward = self.edges.len();
+            self.edges.push(Edge {
+                to: from,
+                cap: 0,
+                cost: -cost,
+            });
+            self.adj[to].push(backward);
+            forward
+        }
+
+        /// Runs successive shortest augmenting paths (by cost, via Dijkstra over Johnson-reduced
+        /// costs) from `source` to `sink` until no improving path remains, returning the total
+        /// flow sent.
+        pub fn solve(&mut self, source: usize, sink: usize) -> i64 {
+            let mut potential = vec![0i64; self.num_nodes];
+            let mut total_flow = 0;
+
+            loop {
+                let mut dist = vec![i64::MAX; self.num_nodes];
+                let mut in_edge: Vec<Option<usize>> = vec![None; self.num_nodes];
+                let mut visited = vec![false; self.num_nodes];
+                dist[source] = 0;
+
+                let mut heap = BinaryHeap::new();
+                heap.push(Reverse((0i64, source)));
+                while let Some(Reverse((d, node))) = heap.pop() {
+                    if visited[node] {
+                        continue;
+                    }
+                    visited[node] = true;
+                    for &edge_idx in &self.adj[node] {
+                        let edge = self.edges[edge_idx];
+                        if edge.cap <= 0 {
+                            continue;
+                        }
+                        // Reduced cost: non-negative as long as `potential` satisfies the
+                        // Johnson invariant established by the previous iteration.
+                        let reduced_cost = edge.cost + potential[node] - potential[edge.to];
+                        let next_dist = d + reduced_cost;
+                        if next_dist < dist[edge.to] {
+                            dist[edge.to] = next_dist;
+                            in_edge[edge.to] = Some(edge_idx);
+                            heap.push(Reverse((next_dist, edge.to)));
+                        }
+                    }
+                }
+
+                if dist[sink] == i64::MAX {
+                    break;
+                }
+                for (node, &d) in dist.iter().enumerate() {
+                    if d < i64::MAX {
+                        potential[node] += d;
+                    }
+                }
+
+                let mut push = i64::MAX;
+                let mut node = sink;
+                while node != source {
+                    let edge_idx = in_edge[node].unwrap();
+                    push = push.min(self.edges[edge_idx].cap);
+                    node = self.edges[edge_idx ^ 1].to;
+                }
+
+                let mut node = sink;
+                while node != source {
+                    let edge_idx = in_edge[node].unwrap();
+                    self.edges[edge_idx].cap -= push;
+                    self.edges[edge_idx ^ 1].cap += push;
+                    node = self.edges[edge_idx ^ 1].to;
+                }
+
+                total_flow += push;
+            }
+            total_flow
+        }
+
+        /// Returns the flow sent along the edge returned by `add_edge`, given that edge's
+        /// original capacity.
+        pub fn flow_on(&self, edge_index: usize, original_cap: i64) -> i64 {
+            original_cap - self.edges[edge_index].cap
+        }
+    }
 }
 
 #[cfg(test)]
@@ -437,4 +1110,247 @@ mod test {
 
         Ok(())
     }
+
+    fn fake_parallel_unit(id: u32, worker_node_id: u32) -> ParallelUnit {
+        ParallelUnit {
+            id,
+            worker_node_id,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_zone_balanced_prefix() {
+        // 6 parallel units spread over 3 workers, 2 zones (zone_a: workers 0,1; zone_b: workers
+        // 2).
+        let parallel_units = (0..6).map(|id| fake_parallel_unit(id, id % 3)).collect_vec();
+        let worker_zones = HashMap::from([
+            (0, "zone_a".to_string()),
+            (1, "zone_a".to_string()),
+            (2, "zone_b".to_string()),
+        ]);
+
+        let scheduler = Scheduler::with_zones(parallel_units, worker_zones, 2);
+        let chosen = scheduler.zone_balanced_prefix(2, 42);
+        let zones = chosen
+            .iter()
+            .map(|p| scheduler.zone_of(p))
+            .collect::<std::collections::HashSet<_>>();
+        // With only 2 units requested and 2 zones available, both zones must be represented.
+        assert_eq!(zones.len(), 2);
+
+        // The same seed always produces the same (reproducible) pick.
+        assert_eq!(chosen, scheduler.zone_balanced_prefix(2, 42));
+
+        // Falls back to the plain ordered prefix when there's only a single zone.
+        let single_zone_scheduler = Scheduler::new((0..4).map(|id| fake_parallel_unit(id, id)));
+        assert_eq!(
+            single_zone_scheduler.zone_balanced_prefix(2, 7),
+            single_zone_scheduler.ordered_parallel_units(7)[..2]
+        );
+    }
+
+    #[test]
+    fn test_ordered_parallel_units_round_robin_without_weights() {
+        // With no real weights plumbed in (the default), ordering must stay the plain
+        // round-robin order regardless of seed, so homogeneous clusters keep spreading a
+        // fragment's actors across workers exactly as a prefix of `all_parallel_units` always
+        // did.
+        let parallel_units = (0..6).map(|id| fake_parallel_unit(id, id)).collect_vec();
+        let scheduler = Scheduler::new(parallel_units.clone());
+        assert_eq!(scheduler.ordered_parallel_units(1), parallel_units);
+        assert_eq!(scheduler.ordered_parallel_units(2), parallel_units);
+    }
+
+    #[test]
+    fn test_weighted_shuffle_favors_heavier_workers() {
+        // Worker 0 is 10x heavier than workers 1..5, so across many fragment ids it should be
+        // picked first far more often than any single lighter worker.
+        let parallel_units = (0..5).map(|id| fake_parallel_unit(id, id)).collect_vec();
+        let worker_weights = HashMap::from([(0, 10.0)]);
+        let scheduler =
+            Scheduler::with_zones_and_weights(parallel_units, HashMap::new(), 1, worker_weights);
+
+        let mut first_pick_counts = HashMap::new();
+        for seed in 0..200u64 {
+            let first = scheduler.weighted_shuffle(seed).remove(0);
+            *first_pick_counts.entry(first.worker_node_id).or_insert(0) += 1;
+        }
+        let heavy_count = *first_pick_counts.get(&0).unwrap_or(&0);
+        let lightest_other = first_pick_counts
+            .iter()
+            .filter(|(&worker, _)| worker != 0)
+            .map(|(_, &count)| count)
+            .max()
+            .unwrap_or(0);
+        assert!(heavy_count > lightest_other);
+    }
+
+    #[test]
+    fn test_rebalance_vnode_mapping_minimizes_reassignment() {
+        let initial_units = (0..4).map(|id| fake_parallel_unit(id, id)).collect_vec();
+        let scheduler = Scheduler::new(initial_units.clone());
+        let initial_mapping = scheduler.rebalance_vnode_mapping(&initial_units, None);
+
+        // Scale up from 4 to 5 parallel units; most vnodes should keep their original unit.
+        let scaled_units = (0..5).map(|id| fake_parallel_unit(id, id)).collect_vec();
+        let scaled_mapping =
+            scheduler.rebalance_vnode_mapping(&scaled_units, Some(&initial_mapping));
+
+        let initial_bitmaps = initial_mapping.to_bitmaps();
+        let scaled_bitmaps = scaled_mapping.to_bitmaps();
+        let mut moved = 0;
+        for vnode in 0..VirtualNode::COUNT {
+            let previous_unit = initial_bitmaps
+                .iter()
+                .find(|(_, bitmap)| bitmap.iter().nth(vnode) == Some(true))
+                .map(|(id, _)| *id);
+            let new_unit = scaled_bitmaps
+                .iter()
+                .find(|(_, bitmap)| bitmap.iter().nth(vnode) == Some(true))
+                .map(|(id, _)| *id);
+            if previous_unit != new_unit {
+                moved += 1;
+            }
+        }
+
+        // Only the new unit's ideal share should need to move in (plus rounding slack); far
+        // fewer than a full reshuffle of all vnodes.
+        assert!(moved <= VirtualNode::COUNT / 4 + 1);
+    }
+
+    #[test]
+    fn test_rebalance_vnode_mapping_minimizes_reassignment_on_scale_down() {
+        // Scaling down exercises a different path through the Johnson-potential augmenting
+        // search than scaling up: a unit disappearing entirely makes every vnode it used to own
+        // unreachable via its old edge, so the solver must re-route all of them through fresh
+        // augmenting paths without leaving any node's potential stale.
+        let initial_units = (0..5).map(|id| fake_parallel_unit(id, id)).collect_vec();
+        let scheduler = Scheduler::new(initial_units.clone());
+        let initial_mapping = scheduler.rebalance_vnode_mapping(&initial_units, None);
+
+        let scaled_units = (0..4).map(|id| fake_parallel_unit(id, id)).collect_vec();
+        let scaled_mapping =
+            scheduler.rebalance_vnode_mapping(&scaled_units, Some(&initial_mapping));
+
+        let initial_bitmaps = initial_mapping.to_bitmaps();
+        let scaled_bitmaps = scaled_mapping.to_bitmaps();
+
+        // Every vnode must land on one of the surviving units.
+        for (unit_id, bitmap) in &scaled_bitmaps {
+            assert!(scaled_units.iter().any(|p| p.id == *unit_id));
+            assert!(bitmap.iter().any(|is_set| is_set));
+        }
+
+        let mut moved = 0;
+        for vnode in 0..VirtualNode::COUNT {
+            let previous_unit = initial_bitmaps
+                .iter()
+                .find(|(_, bitmap)| bitmap.iter().nth(vnode) == Some(true))
+                .map(|(id, _)| *id);
+            let new_unit = scaled_bitmaps
+                .iter()
+                .find(|(_, bitmap)| bitmap.iter().nth(vnode) == Some(true))
+                .map(|(id, _)| *id);
+            if previous_unit != new_unit {
+                moved += 1;
+            }
+        }
+
+        // Only the removed unit's vnodes (plus rounding slack) should need to move; vnodes that
+        // already belonged to a surviving unit must stay put.
+        assert!(moved <= VirtualNode::COUNT / 4 + 1);
+    }
+
+    #[test]
+    fn test_staged_schedule_diff_commit_revert() {
+        let mut applied = ScheduledLocations::new();
+        applied
+            .actor_locations
+            .insert(1, fake_parallel_unit(0, 100));
+        applied
+            .actor_locations
+            .insert(2, fake_parallel_unit(1, 101));
+
+        let mut staged_schedule = StagedSchedule::new(applied);
+        assert_eq!(staged_schedule.layout_version(), 0);
+        assert!(staged_schedule.diff().is_none());
+
+        // Propose moving actor 2 from worker 101 to worker 102, and adding a new actor 3.
+        let mut proposed = ScheduledLocations::new();
+        proposed
+            .actor_locations
+            .insert(1, fake_parallel_unit(0, 100));
+        proposed
+            .actor_locations
+            .insert(2, fake_parallel_unit(2, 102));
+        proposed
+            .actor_locations
+            .insert(3, fake_parallel_unit(3, 102));
+        staged_schedule.stage(proposed);
+
+        let diff = staged_schedule.diff().unwrap();
+        assert_eq!(diff.actors_removed.get(&101), Some(&vec![2]));
+        assert_eq!(diff.actors_added.get(&102).map(|a| a.len()), Some(2));
+        assert_eq!(diff.vnodes_moved, 1); // actor 2 changed parallel unit.
+        assert!(diff.estimated_migration_cost > 0);
+
+        // Reverting discards the proposal and leaves the applied layout untouched.
+        staged_schedule.revert();
+        assert!(staged_schedule.diff().is_none());
+        assert_eq!(staged_schedule.applied().actor_locations.len(), 2);
+
+        // Staging again and committing swaps in the new layout and bumps the version.
+        let mut proposed_again = ScheduledLocations::new();
+        proposed_again
+            .actor_locations
+            .insert(1, fake_parallel_unit(0, 100));
+        proposed_again
+            .actor_locations
+            .insert(2, fake_parallel_unit(2, 102));
+        staged_schedule.stage(proposed_again);
+        assert_eq!(staged_schedule.commit(), 1);
+        assert!(staged_schedule.staged().is_none());
+        assert_eq!(
+            staged_schedule.applied().actor_locations[&2].worker_node_id,
+            102
+        );
+    }
+
+    #[test]
+    fn test_draining_worker_excluded_and_reschedule_off() {
+        let parallel_units = (0..6).map(|id| fake_parallel_unit(id, id % 3)).collect_vec();
+        let draining_workers = HashSet::from([1u32]);
+        let scheduler = Scheduler::with_params(
+            parallel_units,
+            HashMap::new(),
+            1,
+            HashMap::new(),
+            draining_workers,
+        );
+
+        // Worker 1's parallel units (ids 1 and 4) must never be handed out for new placement.
+        assert!(scheduler
+            .all_parallel_units
+            .iter()
+            .all(|p| p.worker_node_id != 1));
+
+        // Actors currently on the now-draining worker 1 must be moved elsewhere; everything else
+        // stays put.
+        let mut locations = ScheduledLocations::new();
+        locations
+            .actor_locations
+            .insert(10, fake_parallel_unit(1, 1));
+        locations
+            .actor_locations
+            .insert(11, fake_parallel_unit(4, 1));
+        locations
+            .actor_locations
+            .insert(12, fake_parallel_unit(0, 0));
+
+        let rescheduled = scheduler.reschedule_off(&locations, 1).unwrap();
+        assert_eq!(rescheduled.actor_locations[&12].worker_node_id, 0);
+        assert_ne!(rescheduled.actor_locations[&10].worker_node_id, 1);
+        assert_ne!(rescheduled.actor_locations[&11].worker_node_id, 1);
+    }
 }